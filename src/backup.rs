@@ -0,0 +1,208 @@
+use crate::config::ResolvedConfig;
+use crate::native;
+use crate::util::{dir_size, ensure_dir, timestamp_id, unix_now};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// One file touched by a sync back. `existed` is false when the sync created
+/// it, in which case undo removes it again.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BackupEntry {
+    pub path: PathBuf,
+    pub existed: bool,
+}
+
+/// What a single sync back replaced on disk. The previous copies live next to
+/// the manifest under `files/`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Backup {
+    pub id: String,
+    pub created: u64,
+    pub entries: Vec<BackupEntry>,
+}
+
+impl Backup {
+    pub fn root(cfg: &ResolvedConfig) -> PathBuf {
+        cfg.state_dir().join("backups")
+    }
+
+    pub fn dir(&self, cfg: &ResolvedConfig) -> PathBuf {
+        Self::root(cfg).join(&self.id)
+    }
+
+    /// Copies the current disk version of every project-relative path into a
+    /// new backup. Returns `None` when there is nothing to save.
+    pub fn create(cfg: &ResolvedConfig, paths: &[PathBuf]) -> Result<Option<Self>> {
+        if paths.is_empty() {
+            return Ok(None);
+        }
+        let created = unix_now();
+        let backup = Backup {
+            id: timestamp_id(&Self::root(cfg), created),
+            created,
+            entries: vec![],
+        };
+        let files = backup.dir(cfg).join("files");
+        ensure_dir(&files)?;
+        let mut entries = Vec::with_capacity(paths.len());
+        for path in paths {
+            let existed = native::copy_path(&cfg.orig_root.join(path), &files.join(path))?;
+            entries.push(BackupEntry {
+                path: path.clone(),
+                existed,
+            });
+        }
+        let backup = Backup { entries, ..backup };
+        backup.save(cfg)?;
+        Ok(Some(backup))
+    }
+
+    fn save(&self, cfg: &ResolvedConfig) -> Result<()> {
+        let path = self.dir(cfg).join("manifest.json");
+        fs::write(&path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join("manifest.json");
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    /// All backups, oldest first.
+    pub fn list(cfg: &ResolvedConfig) -> Result<Vec<Self>> {
+        let root = Self::root(cfg);
+        let dir = match fs::read_dir(&root) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", root.display())),
+        };
+        let mut backups = Vec::new();
+        for entry in dir {
+            let path = entry?.path();
+            match Self::load(&path) {
+                Ok(backup) => backups.push(backup),
+                Err(e) => debug!("skipping {}: {e:#}", path.display()),
+            }
+        }
+        backups.sort_by(|a, b| (a.created, &a.id).cmp(&(b.created, &b.id)));
+        Ok(backups)
+    }
+
+    /// Looks up a backup by id, or the most recent one.
+    pub fn find(cfg: &ResolvedConfig, id: Option<&str>) -> Result<Self> {
+        let backups = Self::list(cfg)?;
+        let found = match id {
+            Some(id) => backups.into_iter().find(|b| b.id == id),
+            None => backups.into_iter().last(),
+        };
+        match (found, id) {
+            (Some(backup), _) => Ok(backup),
+            (None, Some(id)) => bail!("no backup named {id}"),
+            (None, None) => bail!("no backups recorded yet"),
+        }
+    }
+
+    /// Total size of the saved copies.
+    pub fn size(&self, cfg: &ResolvedConfig) -> u64 {
+        dir_size(&self.dir(cfg).join("files"))
+    }
+
+    /// Puts the disk back the way it was before this sync: saved files are
+    /// copied back and files the sync created are removed.
+    pub fn restore(&self, cfg: &ResolvedConfig) -> Result<()> {
+        let files = self.dir(cfg).join("files");
+        for entry in &self.entries {
+            let dest = cfg.orig_root.join(&entry.path);
+            native::remove_entry(&dest)?;
+            if entry.existed {
+                native::copy_path(&files.join(&entry.path), &dest)?;
+            }
+        }
+        info!(
+            "restored {} files from backup {}",
+            self.entries.len(),
+            self.id
+        );
+        Ok(())
+    }
+
+    /// Folds the backup of one continuous-sync flush into the backup of its
+    /// watch session, named `session`. Files the session already saved keep
+    /// their older copy, so undo returns to the disk state before the session.
+    pub fn merge_into_session(self, cfg: &ResolvedConfig, session: &str) -> Result<()> {
+        if self.id == session {
+            return Ok(());
+        }
+        let mut target = match Self::load(&Self::root(cfg).join(session)) {
+            Ok(target) => target,
+            Err(_) => {
+                // The first flush of the session becomes the session backup.
+                let dir = Self::root(cfg).join(session);
+                fs::rename(self.dir(cfg), &dir)
+                    .with_context(|| format!("failed to create {}", dir.display()))?;
+                let backup = Backup {
+                    id: session.to_string(),
+                    ..self
+                };
+                return backup.save(cfg);
+            }
+        };
+        let from = self.dir(cfg).join("files");
+        let to = target.dir(cfg).join("files");
+        for entry in &self.entries {
+            if target.entries.iter().any(|e| e.path == entry.path) {
+                continue;
+            }
+            if entry.existed {
+                let dest = to.join(&entry.path);
+                if let Some(parent) = dest.parent() {
+                    ensure_dir(parent)?;
+                }
+                fs::rename(from.join(&entry.path), &dest)
+                    .with_context(|| format!("failed to move {}", dest.display()))?;
+            }
+            target.entries.push(entry.clone());
+        }
+        target.save(cfg)?;
+        self.remove(cfg)
+    }
+
+    pub fn remove(&self, cfg: &ResolvedConfig) -> Result<()> {
+        let dir = self.dir(cfg);
+        fs::remove_dir_all(&dir).with_context(|| format!("failed to remove {}", dir.display()))
+    }
+}
+
+/// Id for the single backup a watch session keeps; see
+/// `Backup::merge_into_session`.
+pub fn session_id(cfg: &ResolvedConfig) -> String {
+    format!("{}-watch", timestamp_id(&Backup::root(cfg), unix_now()))
+}
+
+/// Applies `backup.keep` and `backup.max_age_days` (or an explicit `keep`),
+/// returning the ids that were removed.
+pub fn prune(cfg: &ResolvedConfig, keep: Option<usize>) -> Result<Vec<String>> {
+    let policy = &cfg.raw.backup;
+    // The automatic prune after a sync always spares the backup it just made.
+    let keep = keep.unwrap_or(policy.keep.max(1));
+    let cutoff = policy
+        .max_age_days
+        .map(|days| unix_now().saturating_sub(days * 86_400));
+    let backups = Backup::list(cfg)?;
+    let excess = backups.len().saturating_sub(keep);
+    let mut removed = Vec::new();
+    for (index, backup) in backups.iter().enumerate() {
+        let too_old = cutoff.is_some_and(|cutoff| backup.created < cutoff);
+        if index < excess || too_old {
+            backup.remove(cfg)?;
+            removed.push(backup.id.clone());
+        }
+    }
+    Ok(removed)
+}
@@ -0,0 +1,128 @@
+use crate::checkpoint::Checkpoint;
+use crate::config::{BudgetPolicy, ResolvedConfig};
+use crate::native;
+use crate::syncer::{source_options, SyncOptions};
+use crate::util::{dir_size, format_bytes, fs_status, is_empty_dir};
+use anyhow::{bail, Result};
+use std::fmt::Write;
+
+/// Share of the budget above which ramws starts warning.
+const NEAR_BUDGET: f64 = 0.9;
+
+/// Bytes a fresh `ramws start` is expected to copy into RAM, per source and
+/// per cache that will be seeded.
+#[derive(Debug, Default)]
+pub struct SizeEstimate {
+    pub parts: Vec<(String, u64)>,
+    pub total: u64,
+}
+
+impl SizeEstimate {
+    fn push(&mut self, label: String, bytes: u64) {
+        self.total += bytes;
+        self.parts.push((label, bytes));
+    }
+
+    fn breakdown(&self) -> String {
+        let width = self.parts.iter().map(|(l, _)| l.len()).max().unwrap_or(0);
+        let mut out = String::new();
+        for (label, bytes) in &self.parts {
+            let _ = write!(out, "\n  {label:<width$}  {}", format_bytes(*bytes));
+        }
+        out
+    }
+}
+
+/// Sums the files each source would copy after its include/exclude filters,
+/// plus the stores of cache dirs that are still empty in RAM.
+pub fn estimate(cfg: &ResolvedConfig) -> Result<SizeEstimate> {
+    let mut estimate = SizeEstimate::default();
+    for source in &cfg.raw.sources {
+        let opts = source_options(cfg, source);
+        let bytes = native::tree_size(&cfg.orig_root.join(&source.path), &opts)?;
+        estimate.push(source.path.display().to_string(), bytes);
+    }
+    for build in &cfg.raw.build_dirs {
+        let Some(store) = cfg.cache_store(build) else {
+            continue;
+        };
+        if store.is_dir() && is_empty_dir(&cfg.workspace_root.join(&build.path))? {
+            let bytes = native::tree_size(&store, &SyncOptions::from_config(&cfg.raw.sync))?;
+            estimate.push(format!("cache {}", build.path.display()), bytes);
+        }
+    }
+    Ok(estimate)
+}
+
+/// Bytes `ramws start --from-checkpoint` restores into RAM.
+pub fn estimate_checkpoint(checkpoint: &Checkpoint) -> SizeEstimate {
+    let mut estimate = SizeEstimate::default();
+    estimate.push(format!("checkpoint {}", checkpoint.id), checkpoint.size());
+    estimate
+}
+
+/// The tighter of the free space at the workspace root and
+/// `workspace.max_size`, with a description of which one it is.
+fn budget(cfg: &ResolvedConfig) -> Result<(u64, String)> {
+    let available = fs_status(&cfg.workspace_root)?.available;
+    let free = (
+        available,
+        format!("free in {}", cfg.workspace_root.display()),
+    );
+    Ok(match cfg.raw.workspace.max_size {
+        Some(max) if max.0 < available => (max.0, "allowed by workspace.max_size".to_string()),
+        _ => free,
+    })
+}
+
+/// Refuses (or, with `workspace.over_budget: warn`, warns) when the estimate
+/// does not fit, and warns when it comes close.
+pub fn check(cfg: &ResolvedConfig, estimate: &SizeEstimate) -> Result<()> {
+    let (limit, what) = budget(cfg)?;
+    let summary = format!(
+        "sources need an estimated {} but only {} is {what}:{}",
+        format_bytes(estimate.total),
+        format_bytes(limit),
+        estimate.breakdown()
+    );
+    if estimate.total > limit {
+        match cfg.raw.workspace.over_budget {
+            BudgetPolicy::Refuse => bail!(
+                "{summary}\nnarrow the sources, raise the limit or set workspace.over_budget: warn"
+            ),
+            BudgetPolicy::Warn => eprintln!("Warning: {summary}"),
+        }
+    } else if estimate.total as f64 > limit as f64 * NEAR_BUDGET {
+        eprintln!("Warning: {summary}");
+    }
+    Ok(())
+}
+
+/// Warnings for `ramws status` once the workspace nears its budget.
+pub fn status_warnings(cfg: &ResolvedConfig) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if !cfg.workspace_root.exists() {
+        return warnings;
+    }
+    if let Some(max) = cfg.raw.workspace.max_size {
+        let used = dir_size(&cfg.workspace_root);
+        if used as f64 > max.0 as f64 * NEAR_BUDGET {
+            warnings.push(format!(
+                "workspace uses {} of its {} max_size",
+                format_bytes(used),
+                format_bytes(max.0)
+            ));
+        }
+    }
+    if let Ok(stat) = fs_status(&cfg.workspace_root) {
+        if stat.total > 0 && stat.used as f64 > stat.total as f64 * NEAR_BUDGET {
+            warnings.push(format!(
+                "{} filesystem is {} of {} full",
+                stat.fs_type,
+                format_bytes(stat.used),
+                format_bytes(stat.total)
+            ));
+        }
+    }
+    warnings
+}
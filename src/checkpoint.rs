@@ -0,0 +1,377 @@
+use crate::config::ResolvedConfig;
+use crate::manifest::Manifest;
+use crate::native;
+use crate::util::{ensure_dir, timestamp_id, unix_now};
+use anyhow::{bail, Context, Result};
+use nix::fcntl::{flock, FlockArg};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use walkdir::WalkDir;
+
+/// Lock file in the checkpoint dir; see `lock`.
+const LOCK_FILE: &str = ".lock";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// One workspace entry as of a checkpoint. File contents live in the snapshot
+/// named by `snapshot`, which is the checkpoint that last saw them change.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CheckpointEntry {
+    pub kind: EntryKind,
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub mode: u32,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub snapshot: String,
+}
+
+impl CheckpointEntry {
+    fn from_metadata(meta: &fs::Metadata) -> Self {
+        let kind = if meta.file_type().is_symlink() {
+            EntryKind::Symlink
+        } else if meta.is_dir() {
+            EntryKind::Dir
+        } else {
+            EntryKind::File
+        };
+        CheckpointEntry {
+            kind,
+            size: meta.len(),
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec(),
+            mode: meta.permissions().mode() & 0o7777,
+            snapshot: String::new(),
+        }
+    }
+
+    /// Same content as `other`, ignoring where it is stored.
+    fn unchanged(&self, other: &CheckpointEntry) -> bool {
+        (self.kind, self.size, self.mtime, self.mtime_nsec, self.mode)
+            == (
+                other.kind,
+                other.size,
+                other.mtime,
+                other.mtime_nsec,
+                other.mode,
+            )
+    }
+}
+
+/// Full listing of `workspace_root` at one point in time. Only entries that
+/// changed since the previous checkpoint are copied into its own snapshot.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Checkpoint {
+    pub id: String,
+    pub created: u64,
+    pub entries: BTreeMap<PathBuf, CheckpointEntry>,
+    /// The sync baseline as it stood when the checkpoint was taken, which is
+    /// what the checkpointed RAM contents have to be compared against.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baseline: Option<Manifest>,
+}
+
+impl Checkpoint {
+    fn files(cfg: &ResolvedConfig, snapshot: &str) -> PathBuf {
+        cfg.checkpoint_dir().join(snapshot).join("files")
+    }
+
+    fn manifest_path(cfg: &ResolvedConfig, id: &str) -> PathBuf {
+        cfg.checkpoint_dir().join(id).join("manifest.json")
+    }
+
+    /// All complete checkpoints, oldest first.
+    pub fn list(cfg: &ResolvedConfig) -> Result<Vec<Self>> {
+        let root = cfg.checkpoint_dir();
+        let dir = match fs::read_dir(&root) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", root.display())),
+        };
+        let mut checkpoints = Vec::new();
+        for entry in dir {
+            let entry = entry?;
+            if !is_snapshot_name(&entry.file_name()) {
+                continue;
+            }
+            let path = entry.path().join("manifest.json");
+            let Ok(text) = fs::read_to_string(&path) else {
+                continue;
+            };
+            match serde_json::from_str::<Checkpoint>(&text) {
+                Ok(checkpoint) => checkpoints.push(checkpoint),
+                Err(e) => debug!("skipping {}: {e}", path.display()),
+            }
+        }
+        checkpoints.sort_by(|a, b| (a.created, &a.id).cmp(&(b.created, &b.id)));
+        Ok(checkpoints)
+    }
+
+    pub fn latest(cfg: &ResolvedConfig) -> Result<Option<Self>> {
+        Ok(Self::list(cfg)?.pop())
+    }
+
+    /// Snapshots the workspace, copying only entries that changed since the
+    /// latest checkpoint. The manifest is written last, so an interrupted
+    /// checkpoint is simply ignored.
+    pub fn create(cfg: &ResolvedConfig) -> Result<(Self, usize)> {
+        if !cfg.workspace_root.exists() {
+            bail!(
+                "workspace not found at {}; run ramws start",
+                cfg.workspace_root.display()
+            );
+        }
+        let _lock = lock(cfg)?;
+        let previous = Self::latest(cfg)?.map(|c| c.entries).unwrap_or_default();
+        let baseline = Manifest::load(cfg)?;
+        let root = cfg.checkpoint_dir();
+        let created = unix_now();
+        let id = timestamp_id(&root, created);
+        let files = Self::files(cfg, &id);
+        ensure_dir(&files)?;
+        let mut entries = BTreeMap::new();
+        let mut copied = 0;
+        for item in WalkDir::new(&cfg.workspace_root).min_depth(1) {
+            let item =
+                item.with_context(|| format!("failed to walk {}", cfg.workspace_root.display()))?;
+            let rel = item.path().strip_prefix(&cfg.workspace_root)?.to_path_buf();
+            let meta = item
+                .metadata()
+                .with_context(|| format!("failed to stat {}", item.path().display()))?;
+            let mut entry = CheckpointEntry::from_metadata(&meta);
+            if entry.kind != EntryKind::Dir {
+                match previous.get(&rel) {
+                    Some(old) if old.unchanged(&entry) => entry.snapshot = old.snapshot.clone(),
+                    _ => {
+                        native::copy_path(item.path(), &files.join(&rel))?;
+                        entry.snapshot = id.clone();
+                        copied += 1;
+                    }
+                }
+            }
+            entries.insert(rel, entry);
+        }
+        let checkpoint = Checkpoint {
+            id,
+            created,
+            entries,
+            baseline,
+        };
+        let path = Self::manifest_path(cfg, &checkpoint.id);
+        let tmp = native::tmp_path(&path);
+        fs::write(&tmp, serde_json::to_vec(&checkpoint)?)
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &path).with_context(|| format!("failed to write {}", path.display()))?;
+        prune_locked(cfg)?;
+        Ok((checkpoint, copied))
+    }
+
+    /// Bytes the checkpointed files take up once restored.
+    pub fn size(&self) -> u64 {
+        self.entries
+            .values()
+            .filter(|e| e.kind != EntryKind::Dir)
+            .map(|e| e.size)
+            .sum()
+    }
+
+    /// Makes `workspace_root` match the checkpointed tree, removing anything
+    /// the checkpoint does not list, and puts back the baseline it was taken
+    /// against.
+    pub fn restore(&self, cfg: &ResolvedConfig) -> Result<()> {
+        ensure_dir(&cfg.workspace_root)?;
+        for item in WalkDir::new(&cfg.workspace_root)
+            .contents_first(true)
+            .min_depth(1)
+        {
+            let item =
+                item.with_context(|| format!("failed to walk {}", cfg.workspace_root.display()))?;
+            let rel = item.path().strip_prefix(&cfg.workspace_root)?;
+            let listed = self
+                .entries
+                .get(rel)
+                .is_some_and(|e| (e.kind == EntryKind::Dir) == item.file_type().is_dir());
+            if !listed {
+                native::remove_entry(item.path())?;
+            }
+        }
+        let mut dirs = Vec::new();
+        for (rel, entry) in &self.entries {
+            let dest = cfg.workspace_root.join(rel);
+            if entry.kind == EntryKind::Dir {
+                ensure_dir(&dest)?;
+                dirs.push((dest, entry));
+                continue;
+            }
+            let src = Self::files(cfg, &entry.snapshot).join(rel);
+            if !native::copy_path(&src, &dest)? {
+                warn!("checkpoint {} is missing {}", self.id, rel.display());
+            }
+        }
+        for (dest, entry) in dirs {
+            fs::set_permissions(&dest, fs::Permissions::from_mode(entry.mode))
+                .with_context(|| format!("failed to chmod {}", dest.display()))?;
+        }
+        // A sync back since the checkpoint moved the baseline on; keeping
+        // that one would make the older files look like RAM edits.
+        match &self.baseline {
+            Some(baseline) => baseline.save(cfg)?,
+            None => warn!(
+                "checkpoint {} has no baseline; files synced back since may look edited in RAM",
+                self.id
+            ),
+        }
+        info!(
+            "restored {} entries from checkpoint {}",
+            self.entries.len(),
+            self.id
+        );
+        Ok(())
+    }
+}
+
+/// Held while a checkpoint is written or pruned. A snapshot has no manifest
+/// until it is complete, so a prune running alongside would take it for the
+/// leftovers of an interrupted checkpoint and delete it.
+fn lock(cfg: &ResolvedConfig) -> Result<File> {
+    let root = cfg.checkpoint_dir();
+    ensure_dir(&root)?;
+    let path = root.join(LOCK_FILE);
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    flock(file.as_raw_fd(), FlockArg::LockExclusive)
+        .with_context(|| format!("failed to lock {}", path.display()))?;
+    Ok(file)
+}
+
+/// Drops checkpoints beyond `checkpoint.keep`, then deletes snapshot files no
+/// remaining checkpoint refers to.
+pub fn prune(cfg: &ResolvedConfig) -> Result<()> {
+    let _lock = lock(cfg)?;
+    prune_locked(cfg)
+}
+
+fn prune_locked(cfg: &ResolvedConfig) -> Result<()> {
+    let mut checkpoints = Checkpoint::list(cfg)?;
+    let excess = checkpoints
+        .len()
+        .saturating_sub(cfg.raw.checkpoint.keep.max(1));
+    for old in checkpoints.drain(..excess) {
+        let path = Checkpoint::manifest_path(cfg, &old.id);
+        fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))?;
+    }
+    let referenced: BTreeSet<(&str, &Path)> = checkpoints
+        .iter()
+        .flat_map(|c| c.entries.iter())
+        .filter(|(_, e)| e.kind != EntryKind::Dir)
+        .map(|(rel, e)| (e.snapshot.as_str(), rel.as_path()))
+        .collect();
+    let live: BTreeSet<&str> = checkpoints.iter().map(|c| c.id.as_str()).collect();
+    let root = cfg.checkpoint_dir();
+    for dir in fs::read_dir(&root).with_context(|| format!("failed to read {}", root.display()))? {
+        let dir = dir?;
+        // `checkpoint.dir` may be shared with other things, so only what
+        // looks like one of our snapshots is ever removed.
+        if !dir.file_type()?.is_dir() || !is_snapshot_name(&dir.file_name()) {
+            continue;
+        }
+        let dir = dir.path();
+        let Some(snapshot) = dir.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if live.contains(snapshot) || !looks_like_snapshot(&dir) {
+            continue;
+        }
+        let files = dir.join("files");
+        let mut kept = false;
+        for item in WalkDir::new(&files).contents_first(true).min_depth(1) {
+            let item = item?;
+            let rel = item.path().strip_prefix(&files)?;
+            if item.file_type().is_dir() {
+                // Only succeeds once everything below has been dropped.
+                fs::remove_dir(item.path()).ok();
+            } else if referenced.contains(&(snapshot, rel)) {
+                kept = true;
+            } else {
+                native::remove_entry(item.path())?;
+            }
+        }
+        if !kept {
+            fs::remove_dir_all(&dir)
+                .with_context(|| format!("failed to remove {}", dir.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `name` has the shape `timestamp_id` gives snapshots, e.g.
+/// `20240102T030405Z` or `20240102T030405Z-1`.
+fn is_snapshot_name(name: &std::ffi::OsStr) -> bool {
+    let Some(name) = name.to_str() else {
+        return false;
+    };
+    let (stamp, suffix) = match name.split_once('-') {
+        Some((stamp, n)) => (stamp, Some(n)),
+        None => (name, None),
+    };
+    let b = stamp.as_bytes();
+    b.len() == 16
+        && b[8] == b'T'
+        && b[15] == b'Z'
+        && b[..8].iter().chain(&b[9..15]).all(u8::is_ascii_digit)
+        && suffix.is_none_or(|n| !n.is_empty() && n.bytes().all(|c| c.is_ascii_digit()))
+}
+
+/// Whether `dir` holds what `Checkpoint::create` writes: snapshot files, a
+/// manifest, or the temp file of one still being written.
+fn looks_like_snapshot(dir: &Path) -> bool {
+    let manifest = dir.join("manifest.json");
+    dir.join("files").is_dir() || manifest.is_file() || native::tmp_path(&manifest).is_file()
+}
+
+/// Takes a checkpoint every `checkpoint.interval` seconds on its own thread.
+pub struct BackgroundCheckpoint {
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl BackgroundCheckpoint {
+    pub fn spawn(cfg: ResolvedConfig, interval: Duration) -> Self {
+        let (stop, rx) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(interval) {
+                match Checkpoint::create(&cfg) {
+                    Ok((checkpoint, copied)) => {
+                        info!("checkpoint {}: {copied} files copied", checkpoint.id)
+                    }
+                    Err(e) => warn!("checkpoint failed: {e:#}"),
+                }
+            }
+        });
+        BackgroundCheckpoint { stop, handle }
+    }
+
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.handle.join();
+    }
+}
@@ -1,33 +1,140 @@
-use crate::util::{expand_placeholders, project_slug};
+use crate::util::{expand_placeholders, parse_size, project_slug};
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct WorkspaceSection {
     pub root: Option<String>,
+    #[serde(default)]
+    pub mode: WorkspaceMode,
+    /// Mount a private tmpfs at the workspace root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mount: Option<MountConfig>,
+    /// Most RAM the workspace may use, e.g. `8G`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<ByteSize>,
+    #[serde(default)]
+    pub over_budget: BudgetPolicy,
 }
 
-impl Default for WorkspaceSection {
-    fn default() -> Self {
-        Self { root: None }
+/// What `ramws start` does when the sources would not fit in RAM.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetPolicy {
+    #[default]
+    Refuse,
+    Warn,
+}
+
+/// A byte count written as a number or with a unit (`512M`, `8G`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "ValueRepr", into = "ValueRepr")]
+pub struct ByteSize(pub u64);
+
+impl TryFrom<ValueRepr> for ByteSize {
+    type Error = String;
+
+    fn try_from(repr: ValueRepr) -> std::result::Result<Self, Self::Error> {
+        match repr {
+            ValueRepr::Number(n) => Ok(ByteSize(n)),
+            ValueRepr::Text(text) => parse_size(&text).map(ByteSize).map_err(|e| e.to_string()),
+        }
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+impl From<ByteSize> for ValueRepr {
+    fn from(size: ByteSize) -> Self {
+        ValueRepr::Number(size.0)
+    }
+}
+
+/// How the workspace gets its copy of the project.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Default)]
 #[serde(rename_all = "lowercase")]
-pub enum BuildDirType {
-    Scratch,
-    Cache,
+pub enum WorkspaceMode {
+    /// Sources are copied into RAM.
+    #[default]
+    Copy,
+    /// The project is the lower layer of an overlayfs whose upper layer is in
+    /// RAM, so only files that are written take up memory.
+    Overlay,
 }
 
-impl Default for BuildDirType {
-    fn default() -> Self {
-        BuildDirType::Scratch
+/// Limits for the private tmpfs, passed through as tmpfs mount options.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct MountConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<TmpfsValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nr_inodes: Option<TmpfsValue>,
+}
+
+impl MountConfig {
+    /// The `-o` string handed to mount(2).
+    pub fn options(&self) -> String {
+        let mut opts = vec!["mode=0700".to_string()];
+        if let Some(size) = &self.size {
+            opts.push(format!("size={size}"));
+        }
+        if let Some(inodes) = &self.nr_inodes {
+            opts.push(format!("nr_inodes={inodes}"));
+        }
+        opts.join(",")
     }
 }
 
+/// A tmpfs limit: a number with an optional `k`/`m`/`g`/`t` suffix
+/// (`4G`, `1m`), or a share of RAM (`50%`).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "ValueRepr", into = "ValueRepr")]
+pub struct TmpfsValue(String);
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum ValueRepr {
+    Number(u64),
+    Text(String),
+}
+
+impl TryFrom<ValueRepr> for TmpfsValue {
+    type Error = String;
+
+    fn try_from(repr: ValueRepr) -> std::result::Result<Self, Self::Error> {
+        let text = match repr {
+            ValueRepr::Number(n) => return Ok(TmpfsValue(n.to_string())),
+            ValueRepr::Text(text) => text.trim().to_string(),
+        };
+        let digits = text.trim_end_matches(['k', 'K', 'm', 'M', 'g', 'G', 't', 'T', '%']);
+        let suffix = &text[digits.len()..];
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) || suffix.len() > 1 {
+            return Err(format!("invalid tmpfs limit {text:?}"));
+        }
+        Ok(TmpfsValue(text))
+    }
+}
+
+impl From<TmpfsValue> for ValueRepr {
+    fn from(value: TmpfsValue) -> Self {
+        ValueRepr::Text(value.0)
+    }
+}
+
+impl std::fmt::Display for TmpfsValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildDirType {
+    #[default]
+    Scratch,
+    Cache,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SourceSpec {
     pub path: PathBuf,
@@ -35,6 +142,48 @@ pub struct SourceSpec {
     pub include: Vec<String>,
     #[serde(default)]
     pub exclude: Vec<String>,
+    /// Files `ramws start --lazy` copies up front; the rest stay on disk
+    /// until fetched. Same pattern syntax as `include`/`exclude`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hot: Vec<String>,
+    /// Which files belong to the source before `include`/`exclude` apply.
+    #[serde(default, skip_serializing_if = "SourceSelect::is_all")]
+    pub select: SourceSelect,
+    /// Skip what `.gitignore`, `.ramwsignore`, `.git/info/exclude` and the
+    /// global git excludes file ignore. Off unless set, so existing configs
+    /// keep mirroring ignored files.
+    #[serde(default)]
+    pub ignore_files: bool,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Default)]
+pub enum SourceSelect {
+    /// Everything under the source path.
+    #[default]
+    #[serde(rename = "all")]
+    All,
+    /// Only files in the git index.
+    #[serde(rename = "git-tracked")]
+    GitTracked,
+    /// Files in the git index plus untracked files git does not ignore.
+    #[serde(rename = "git-tracked+untracked-not-ignored")]
+    GitTrackedUntracked,
+}
+
+impl SourceSelect {
+    fn is_all(&self) -> bool {
+        *self == SourceSelect::All
+    }
+}
+
+impl std::fmt::Display for SourceSelect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SourceSelect::All => "all",
+            SourceSelect::GitTracked => "git-tracked",
+            SourceSelect::GitTrackedUntracked => "git-tracked+untracked-not-ignored",
+        })
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -42,19 +191,105 @@ pub struct BuildDirSpec {
     pub path: PathBuf,
     #[serde(default)]
     pub r#type: BuildDirType,
+    /// Persistent home of a cache dir: a path (relative to the project root),
+    /// or `xdg` for `$XDG_CACHE_HOME/ramws/<slug>/<path>`. Defaults to the
+    /// same path in the project on disk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+    /// Replace the directory in the project with a symlink to the RAM copy
+    /// while the workspace exists.
+    #[serde(default)]
+    pub link: bool,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum SyncOnExit {
+    #[default]
     Ask,
     Auto,
     Never,
 }
 
-impl Default for SyncOnExit {
-    fn default() -> Self {
-        SyncOnExit::Ask
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncBackendKind {
+    #[default]
+    Native,
+    Rsync,
+    Auto,
+}
+
+/// How sync back treats files changed both in RAM and on disk since the
+/// workspace was populated. `ours` is the RAM copy, `theirs` the disk copy.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    #[default]
+    Refuse,
+    Ours,
+    Theirs,
+    KeepBoth,
+    Prompt,
+}
+
+/// Upper bound on deletions a single sync back may perform: either a file
+/// count (`200`) or a share of the tracked files (`"10%"`).
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "LimitRepr", into = "LimitRepr")]
+pub enum DeleteLimit {
+    Count(usize),
+    Percent(f64),
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum LimitRepr {
+    Count(usize),
+    Text(String),
+}
+
+impl TryFrom<LimitRepr> for DeleteLimit {
+    type Error = String;
+
+    fn try_from(repr: LimitRepr) -> std::result::Result<Self, Self::Error> {
+        match repr {
+            LimitRepr::Count(n) => Ok(DeleteLimit::Count(n)),
+            LimitRepr::Text(text) => {
+                let text = text.trim();
+                if let Some(pct) = text.strip_suffix('%') {
+                    pct.trim()
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|p| (0.0..=100.0).contains(p))
+                        .map(DeleteLimit::Percent)
+                        .ok_or_else(|| format!("invalid percentage {text:?}"))
+                } else {
+                    text.parse()
+                        .map(DeleteLimit::Count)
+                        .map_err(|_| format!("invalid max_delete {text:?}"))
+                }
+            }
+        }
+    }
+}
+
+impl From<DeleteLimit> for LimitRepr {
+    fn from(limit: DeleteLimit) -> Self {
+        match limit {
+            DeleteLimit::Count(n) => LimitRepr::Count(n),
+            DeleteLimit::Percent(p) => LimitRepr::Text(format!("{p}%")),
+        }
+    }
+}
+
+impl DeleteLimit {
+    /// Resolves the limit to a file count given how many files are tracked.
+    pub fn allowed(&self, tracked: usize) -> usize {
+        match self {
+            DeleteLimit::Count(n) => *n,
+            DeleteLimit::Percent(p) => (tracked as f64 * p / 100.0).floor() as usize,
+        }
     }
 }
 
@@ -64,17 +299,174 @@ pub struct SyncConfig {
     pub on_exit: SyncOnExit,
     #[serde(default = "default_delete")]
     pub delete: bool,
+    #[serde(default)]
+    pub backend: SyncBackendKind,
+    #[serde(default)]
+    pub checksum: bool,
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
+    #[serde(default)]
+    pub noninteractive_conflict: ConflictPolicy,
+    /// Globs (relative to the project root) that sync back never deletes.
+    #[serde(default)]
+    pub protect: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_delete: Option<DeleteLimit>,
+    /// Run the RAM-to-disk watcher alongside `ramws shell`.
+    #[serde(default)]
+    pub continuous: bool,
+    /// Quiet period before a watcher flushes a burst of edits.
+    #[serde(default = "default_debounce_ms")]
+    pub debounce_ms: u64,
 }
 
 fn default_delete() -> bool {
     true
 }
 
+fn default_debounce_ms() -> u64 {
+    500
+}
+
 impl Default for SyncConfig {
     fn default() -> Self {
         SyncConfig {
             on_exit: SyncOnExit::Ask,
             delete: true,
+            backend: SyncBackendKind::Native,
+            checksum: false,
+            conflict: ConflictPolicy::Refuse,
+            noninteractive_conflict: ConflictPolicy::Refuse,
+            protect: vec![],
+            max_delete: None,
+            continuous: false,
+            debounce_ms: default_debounce_ms(),
+        }
+    }
+}
+
+/// Retention for the pre-sync backups under `.ramws/backups`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BackupConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Number of most recent backups to keep.
+    #[serde(default = "default_backup_keep")]
+    pub keep: usize,
+    /// Also drop backups older than this many days.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_days: Option<u64>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_backup_keep() -> usize {
+    20
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        BackupConfig {
+            enabled: true,
+            keep: default_backup_keep(),
+            max_age_days: None,
+        }
+    }
+}
+
+/// Snapshots of the whole workspace (sources and build dirs) on persistent
+/// storage.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CheckpointConfig {
+    /// Seconds between automatic checkpoints while `ramws shell` runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u64>,
+    /// Where snapshots go; defaults to `$XDG_CACHE_HOME/ramws/<slug>/checkpoints`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+    /// Number of most recent checkpoints to keep.
+    #[serde(default = "default_checkpoint_keep")]
+    pub keep: usize,
+}
+
+fn default_checkpoint_keep() -> usize {
+    3
+}
+
+impl Default for CheckpointConfig {
+    fn default() -> Self {
+        CheckpointConfig {
+            interval: None,
+            dir: None,
+            keep: default_checkpoint_keep(),
+        }
+    }
+}
+
+/// What to do when memory pressure stays high.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpillPolicy {
+    /// Only warn.
+    #[default]
+    Never,
+    /// Move the largest scratch build dir to disk, leaving a symlink.
+    LargestScratch,
+}
+
+/// Memory pressure monitoring during `ramws shell` and `ramws watch`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PressureConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// PSI `some avg10` percentage counted as high pressure.
+    #[serde(default = "default_pressure_threshold")]
+    pub threshold: f64,
+    /// Share of RAM (percent) below which `MemAvailable` counts as high
+    /// pressure; used as well as PSI, and instead of it on older kernels.
+    #[serde(default = "default_min_available")]
+    pub min_available: f64,
+    /// Seconds pressure must stay high before ramws acts.
+    #[serde(default = "default_pressure_sustain")]
+    pub sustain: u64,
+    /// Seconds between samples.
+    #[serde(default = "default_pressure_interval")]
+    pub interval: u64,
+    #[serde(default)]
+    pub spill: SpillPolicy,
+    /// Where spilled dirs go; defaults to `$XDG_CACHE_HOME/ramws/<slug>/spill`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spill_dir: Option<String>,
+}
+
+fn default_pressure_threshold() -> f64 {
+    20.0
+}
+
+fn default_min_available() -> f64 {
+    10.0
+}
+
+fn default_pressure_sustain() -> u64 {
+    30
+}
+
+fn default_pressure_interval() -> u64 {
+    5
+}
+
+impl Default for PressureConfig {
+    fn default() -> Self {
+        PressureConfig {
+            enabled: true,
+            threshold: default_pressure_threshold(),
+            min_available: default_min_available(),
+            sustain: default_pressure_sustain(),
+            interval: default_pressure_interval(),
+            spill: SpillPolicy::default(),
+            spill_dir: None,
         }
     }
 }
@@ -85,6 +477,19 @@ pub struct GitConfig {
     pub require_clean: bool,
     #[serde(default)]
     pub auto_stage_synced: bool,
+    #[serde(default)]
+    pub mode: GitMode,
+}
+
+/// How the repository itself is visible inside the workspace.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GitMode {
+    /// The workspace holds no git metadata.
+    #[default]
+    Off,
+    /// The workspace root is a linked worktree of the project's repository.
+    Worktree,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -98,6 +503,12 @@ pub struct Config {
     #[serde(default)]
     pub sync: SyncConfig,
     #[serde(default)]
+    pub backup: BackupConfig,
+    #[serde(default)]
+    pub checkpoint: CheckpointConfig,
+    #[serde(default)]
+    pub pressure: PressureConfig,
+    #[serde(default)]
     pub git: GitConfig,
 }
 
@@ -111,6 +522,9 @@ fn default_sources() -> Vec<SourceSpec> {
             "target/**".to_string(),
             "node_modules/**".to_string(),
         ],
+        hot: vec![],
+        select: SourceSelect::All,
+        ignore_files: false,
     }]
 }
 
@@ -121,6 +535,9 @@ impl Default for Config {
             sources: default_sources(),
             build_dirs: vec![],
             sync: SyncConfig::default(),
+            backup: BackupConfig::default(),
+            checkpoint: CheckpointConfig::default(),
+            pressure: PressureConfig::default(),
             git: GitConfig::default(),
         }
     }
@@ -135,6 +552,86 @@ pub struct ResolvedConfig {
     pub raw: Config,
 }
 
+impl ResolvedConfig {
+    /// Directory under the project root holding ramws bookkeeping.
+    pub fn state_dir(&self) -> PathBuf {
+        self.orig_root.join(".ramws")
+    }
+
+    pub fn checkpoint_dir(&self) -> PathBuf {
+        match &self.raw.checkpoint.dir {
+            Some(dir) => self
+                .orig_root
+                .join(expand_placeholders(dir, &self.project_slug)),
+            None => dirs::cache_dir()
+                .unwrap_or_else(|| self.state_dir())
+                .join("ramws")
+                .join(&self.project_slug)
+                .join("checkpoints"),
+        }
+    }
+
+    pub fn spill_dir(&self) -> PathBuf {
+        match &self.raw.pressure.spill_dir {
+            Some(dir) => self
+                .orig_root
+                .join(expand_placeholders(dir, &self.project_slug)),
+            None => dirs::cache_dir()
+                .unwrap_or_else(|| self.state_dir())
+                .join("ramws")
+                .join(&self.project_slug)
+                .join("spill"),
+        }
+    }
+
+    /// Where the project's own copy of a linked build dir is kept while the
+    /// symlink is in place.
+    pub fn linked_aside(&self, build: &BuildDirSpec) -> PathBuf {
+        self.state_dir().join("linked").join(&build.path)
+    }
+
+    /// Persistent location a cache build dir is seeded from and saved to;
+    /// `None` for scratch dirs.
+    pub fn cache_store(&self, build: &BuildDirSpec) -> Option<PathBuf> {
+        if build.r#type != BuildDirType::Cache {
+            return None;
+        }
+        Some(match build.store.as_deref() {
+            None if build.link => self.linked_aside(build),
+            None => self.orig_root.join(&build.path),
+            Some("xdg") => dirs::cache_dir()
+                .unwrap_or_else(|| self.state_dir())
+                .join("ramws")
+                .join(&self.project_slug)
+                .join(&build.path),
+            Some(dir) => self
+                .orig_root
+                .join(expand_placeholders(dir, &self.project_slug)),
+        })
+    }
+
+    /// Where sync back freezes the RAM side before touching disk: next to the
+    /// workspace (so on the same RAM filesystem) rather than inside any source.
+    pub fn staging_dir(&self) -> PathBuf {
+        self.workspace_sibling(".staging")
+    }
+
+    /// Upper and work dirs of the overlay in `overlay` mode.
+    pub fn overlay_dir(&self) -> PathBuf {
+        self.workspace_sibling(".overlay")
+    }
+
+    fn workspace_sibling(&self, suffix: &str) -> PathBuf {
+        let mut name = self
+            .workspace_root
+            .file_name()
+            .unwrap_or_default()
+            .to_os_string();
+        name.push(suffix);
+        self.workspace_root.with_file_name(name)
+    }
+}
+
 impl Config {
     pub fn load_from_file(path: &Path, orig_root: PathBuf) -> Result<ResolvedConfig> {
         let text = fs::read_to_string(path)
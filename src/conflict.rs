@@ -0,0 +1,188 @@
+use crate::config::{ConflictPolicy, SyncConfig};
+use crate::manifest::Manifest;
+use crate::patch::patch_between;
+use crate::util::prompt_select;
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::warn;
+
+/// Which side moved away from the baseline for a file that differs between
+/// RAM and disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeOrigin {
+    Ram,
+    Disk,
+    Conflict,
+}
+
+/// Classifies `path` (project-relative) given its RAM and disk locations.
+/// Without a baseline every difference is attributed to RAM, which is how
+/// sync back behaved before manifests were recorded.
+pub fn classify(
+    baseline: Option<&Manifest>,
+    path: &Path,
+    ram: &Path,
+    disk: &Path,
+) -> Result<ChangeOrigin> {
+    let Some(baseline) = baseline else {
+        return Ok(ChangeOrigin::Ram);
+    };
+    let (ram_changed, disk_changed) = match baseline.entries.get(path) {
+        Some(entry) => (!entry.matches(ram)?, !entry.matches(disk)?),
+        None => (
+            fs::symlink_metadata(ram).is_ok(),
+            fs::symlink_metadata(disk).is_ok(),
+        ),
+    };
+    Ok(match (ram_changed, disk_changed) {
+        (true, true) => ChangeOrigin::Conflict,
+        (false, true) => ChangeOrigin::Disk,
+        _ => ChangeOrigin::Ram,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Write the RAM copy to disk.
+    Ours,
+    /// Keep the disk copy and pull it into RAM.
+    Theirs,
+    /// Leave the disk copy in place and write both versions next to it.
+    KeepBoth,
+}
+
+/// A file changed both in RAM and on disk. `ram` is the snapshot that would
+/// be written back, `workspace` the live file inside the RAM workspace.
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub path: PathBuf,
+    pub ram: PathBuf,
+    pub disk: PathBuf,
+    pub workspace: PathBuf,
+}
+
+/// Picks a resolution for each conflict according to the configured policy.
+/// A `prompt` policy falls back to `sync.noninteractive_conflict` when no
+/// terminal interaction is allowed.
+pub fn resolve(
+    sync: &SyncConfig,
+    conflicts: &[Conflict],
+    noninteractive: bool,
+) -> Result<Vec<Resolution>> {
+    let mut policy = sync.conflict;
+    if policy == ConflictPolicy::Prompt && noninteractive {
+        policy = sync.noninteractive_conflict;
+        eprintln!(
+            "noninteractive: applying conflict policy {:?} to {} file(s):",
+            policy,
+            conflicts.len()
+        );
+        for conflict in conflicts {
+            eprintln!("  {}", conflict.path.display());
+        }
+    }
+    let all = |r: Resolution| Ok(vec![r; conflicts.len()]);
+    match policy {
+        ConflictPolicy::Ours => all(Resolution::Ours),
+        ConflictPolicy::Theirs => all(Resolution::Theirs),
+        ConflictPolicy::KeepBoth => all(Resolution::KeepBoth),
+        ConflictPolicy::Prompt if !noninteractive => conflicts.iter().map(prompt_one).collect(),
+        ConflictPolicy::Refuse | ConflictPolicy::Prompt => {
+            let list: Vec<String> = conflicts
+                .iter()
+                .map(|c| format!("  {}", c.path.display()))
+                .collect();
+            bail!(
+                "refusing to sync back: {} file(s) changed both in RAM and on disk:\n{}\n\
+                 set sync.conflict to ours, theirs, keep-both or prompt to resolve",
+                conflicts.len(),
+                list.join("\n")
+            )
+        }
+    }
+}
+
+const PROMPT_ITEMS: [&str; 5] = [
+    "keep RAM copy",
+    "keep disk copy",
+    "view diff",
+    "open $MERGETOOL",
+    "write both copies (.ramws-ours / .ramws-theirs)",
+];
+
+fn prompt_one(conflict: &Conflict) -> Result<Resolution> {
+    loop {
+        let choice = prompt_select(
+            &format!("{} changed in RAM and on disk", conflict.path.display()),
+            &PROMPT_ITEMS,
+        )?;
+        match choice {
+            0 => return Ok(Resolution::Ours),
+            1 => return Ok(Resolution::Theirs),
+            2 => print!(
+                "{}",
+                patch_between(&conflict.path, &conflict.disk, &conflict.ram)?
+            ),
+            3 => run_mergetool(conflict)?,
+            _ => return Ok(Resolution::KeepBoth),
+        }
+    }
+}
+
+/// Opens `$MERGETOOL <ram> <disk>` on the live workspace file, then refreshes
+/// the snapshot so a subsequent "keep RAM copy" writes the merged result.
+fn run_mergetool(conflict: &Conflict) -> Result<()> {
+    let Ok(tool) = env::var("MERGETOOL") else {
+        warn!("MERGETOOL is not set");
+        return Ok(());
+    };
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{tool} \"$1\" \"$2\""))
+        .arg("ramws-mergetool")
+        .arg(&conflict.workspace)
+        .arg(&conflict.disk)
+        .status()
+        .with_context(|| format!("failed to launch {tool}"))?;
+    if !status.success() {
+        warn!("{tool} exited with {status}");
+    }
+    if conflict.workspace.is_file() {
+        fs::copy(&conflict.workspace, &conflict.ram)
+            .with_context(|| format!("failed to refresh {}", conflict.ram.display()))?;
+    }
+    Ok(())
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+const KEEP_BOTH_SUFFIXES: [&str; 2] = [".ramws-ours", ".ramws-theirs"];
+
+/// The files `keep_both` may write for `path`, so a backup can cover them.
+pub fn keep_both_paths(path: &Path) -> Vec<PathBuf> {
+    KEEP_BOTH_SUFFIXES
+        .iter()
+        .map(|suffix| sibling(path, suffix))
+        .collect()
+}
+
+/// Writes `<file>.ramws-ours` (RAM) and `<file>.ramws-theirs` (disk) next to
+/// the disk copy, skipping whichever side no longer exists.
+pub fn keep_both(ram: &Path, disk: &Path) -> Result<()> {
+    for (from, suffix) in [ram, disk].into_iter().zip(KEEP_BOTH_SUFFIXES) {
+        if from.is_file() {
+            let to = sibling(disk, suffix);
+            fs::copy(from, &to).with_context(|| format!("failed to write {}", to.display()))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,804 @@
+use crate::config::{ResolvedConfig, SourceSelect};
+use crate::native::{self, Filter};
+use crate::util::{find_in_path, is_empty_dir};
+use anyhow::{bail, Context, Result};
+use sha1::{Digest, Sha1};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use tracing::{debug, info, warn};
+use walkdir::WalkDir;
+
+/// How many dirty paths an error lists before summarising the rest.
+const LISTED: usize = 20;
+
+/// The git work tree containing the project.
+#[derive(Debug, Clone)]
+pub struct Repo {
+    pub work_tree: PathBuf,
+    pub git_dir: PathBuf,
+}
+
+impl Repo {
+    /// Looks for `.git` in `start` and its ancestors. A `.git` file (linked
+    /// worktrees, submodules) points at the real git dir.
+    pub fn discover(start: &Path) -> Option<Self> {
+        for dir in start.ancestors() {
+            let dot_git = dir.join(".git");
+            if dot_git.is_dir() {
+                return Some(Repo {
+                    work_tree: dir.to_path_buf(),
+                    git_dir: dot_git,
+                });
+            }
+            if let Ok(text) = fs::read_to_string(&dot_git) {
+                let target = text.trim().strip_prefix("gitdir:")?.trim();
+                return Some(Repo {
+                    work_tree: dir.to_path_buf(),
+                    git_dir: dir.join(target),
+                });
+            }
+        }
+        None
+    }
+
+    /// Work-tree-relative paths of tracked files with uncommitted changes.
+    /// Uses `git status` when git is installed; otherwise compares the work
+    /// tree with the index, which misses changes that are already staged.
+    pub fn dirty(&self) -> Result<BTreeSet<PathBuf>> {
+        match find_in_path("git") {
+            Some(git) => self.dirty_from_status(&git),
+            None => {
+                debug!(
+                    "git not found; reading {}",
+                    self.git_dir.join("index").display()
+                );
+                self.dirty_from_index()
+            }
+        }
+    }
+
+    fn dirty_from_status(&self, git: &Path) -> Result<BTreeSet<PathBuf>> {
+        let output = Command::new(git)
+            .arg("-C")
+            .arg(&self.work_tree)
+            .args(["status", "--porcelain=v1", "-z", "--untracked-files=no"])
+            .output()
+            .context("failed to run git status")?;
+        if !output.status.success() {
+            bail!(
+                "git status failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let mut dirty = BTreeSet::new();
+        let mut fields = output.stdout.split(|b| *b == 0).filter(|f| !f.is_empty());
+        while let Some(field) = fields.next() {
+            if field.len() < 4 {
+                continue;
+            }
+            dirty.insert(PathBuf::from(std::ffi::OsStr::from_bytes(&field[3..])));
+            // Renames and copies are followed by their source path.
+            if matches!(field[0], b'R' | b'C') {
+                if let Some(from) = fields.next() {
+                    dirty.insert(PathBuf::from(std::ffi::OsStr::from_bytes(from)));
+                }
+            }
+        }
+        Ok(dirty)
+    }
+
+    fn dirty_from_index(&self) -> Result<BTreeSet<PathBuf>> {
+        let path = self.git_dir.join("index");
+        let data = fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        let mut dirty = BTreeSet::new();
+        for entry in
+            parse_index(&data).with_context(|| format!("failed to parse {}", path.display()))?
+        {
+            if entry.is_dirty(&self.work_tree)? {
+                dirty.insert(entry.path);
+            }
+        }
+        Ok(dirty)
+    }
+
+    /// Runs git in the work tree with `stdin_paths` NUL-separated on stdin
+    /// and returns its stdout. Exit codes other
+    /// than those in `ok` are errors.
+    fn run(
+        &self,
+        git: &Path,
+        args: &[&str],
+        stdin_paths: &[PathBuf],
+        ok: &[i32],
+    ) -> Result<Vec<u8>> {
+        let mut child = Command::new(git)
+            .arg("-C")
+            .arg(&self.work_tree)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to run git {}", args.join(" ")))?;
+        let mut stdin = child.stdin.take().context("git stdin unavailable")?;
+        let mut input = Vec::new();
+        for path in stdin_paths {
+            input.extend_from_slice(path.as_os_str().as_bytes());
+            input.push(0);
+        }
+        // Fed from a thread: git may answer (check-ignore) before reading
+        // everything, and a full stdout pipe would block both sides.
+        let feeder = thread::spawn(move || stdin.write_all(&input));
+        let output = child.wait_with_output()?;
+        feeder
+            .join()
+            .map_err(|_| anyhow::anyhow!("git stdin writer panicked"))?
+            .context("failed to write to git")?;
+        if !output.status.code().is_some_and(|c| ok.contains(&c)) {
+            bail!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// One entry of the git index, reduced to what dirty detection needs.
+#[derive(Debug)]
+struct IndexEntry {
+    mtime: (u32, u32),
+    mode: u32,
+    size: u32,
+    oid: [u8; 20],
+    stage: u16,
+    skip: bool,
+    path: PathBuf,
+}
+
+impl IndexEntry {
+    fn is_dirty(&self, work_tree: &Path) -> Result<bool> {
+        // Gitlinks (submodules) and entries git is told not to check.
+        if self.skip || self.mode & 0o170000 == 0o160000 {
+            return Ok(false);
+        }
+        if self.stage != 0 {
+            return Ok(true);
+        }
+        let file = work_tree.join(&self.path);
+        let Ok(meta) = fs::symlink_metadata(&file) else {
+            return Ok(true);
+        };
+        if meta.len() as u32 != self.size {
+            return Ok(true);
+        }
+        if (meta.mtime() as u32, meta.mtime_nsec() as u32) == self.mtime {
+            return Ok(false);
+        }
+        let content = if meta.file_type().is_symlink() {
+            fs::read_link(&file)?.as_os_str().as_bytes().to_vec()
+        } else {
+            fs::read(&file).with_context(|| format!("failed to read {}", file.display()))?
+        };
+        let mut hasher = Sha1::new();
+        hasher.update(format!("blob {}\0", content.len()).as_bytes());
+        hasher.update(&content);
+        Ok(hasher.finalize().as_slice() != self.oid)
+    }
+}
+
+/// Parses index versions 2 to 4 (see gitformat-index(5)).
+fn parse_index(data: &[u8]) -> Result<Vec<IndexEntry>> {
+    let u32_at = |pos: usize| -> Result<u32> {
+        let bytes = data.get(pos..pos + 4).context("truncated index")?;
+        Ok(u32::from_be_bytes(bytes.try_into()?))
+    };
+    let u16_at = |pos: usize| -> Result<u16> {
+        let bytes = data.get(pos..pos + 2).context("truncated index")?;
+        Ok(u16::from_be_bytes(bytes.try_into()?))
+    };
+    if data.get(..4) != Some(b"DIRC") {
+        bail!("not a git index");
+    }
+    let version = u32_at(4)?;
+    if !(2..=4).contains(&version) {
+        bail!("unsupported index version {version}");
+    }
+    let count = u32_at(8)? as usize;
+    let mut entries = Vec::with_capacity(count);
+    let mut pos = 12;
+    let mut previous: Vec<u8> = Vec::new();
+    for _ in 0..count {
+        let start = pos;
+        let mtime = (u32_at(pos + 8)?, u32_at(pos + 12)?);
+        let mode = u32_at(pos + 24)?;
+        let size = u32_at(pos + 36)?;
+        let oid: [u8; 20] = data
+            .get(pos + 40..pos + 60)
+            .context("truncated index")?
+            .try_into()?;
+        let flags = u16_at(pos + 60)?;
+        pos += 62;
+        let mut skip = flags & 0x8000 != 0;
+        if flags & 0x4000 != 0 && version >= 3 {
+            // skip-worktree
+            skip |= u16_at(pos)? & 0x4000 != 0;
+            pos += 2;
+        }
+        let name = if version == 4 {
+            // The path is stored as "drop N bytes of the previous path, then
+            // append this NUL-terminated suffix".
+            let mut strip = 0usize;
+            loop {
+                let byte = *data.get(pos).context("truncated index")?;
+                pos += 1;
+                strip = (strip << 7) | (byte & 0x7f) as usize;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+                strip += 1;
+            }
+            let end = pos
+                + data[pos..]
+                    .iter()
+                    .position(|b| *b == 0)
+                    .context("truncated index")?;
+            let mut name = previous[..previous.len().saturating_sub(strip)].to_vec();
+            name.extend_from_slice(&data[pos..end]);
+            pos = end + 1;
+            name
+        } else {
+            let end = pos
+                + data[pos..]
+                    .iter()
+                    .position(|b| *b == 0)
+                    .context("truncated index")?;
+            let name = data[pos..end].to_vec();
+            // Entries are NUL-padded to a multiple of eight bytes.
+            pos = start + (end - start + 8) / 8 * 8;
+            name
+        };
+        entries.push(IndexEntry {
+            mtime,
+            mode,
+            size,
+            oid,
+            stage: (flags >> 12) & 0x3,
+            skip,
+            path: PathBuf::from(std::ffi::OsStr::from_bytes(&name)),
+        });
+        previous = name;
+    }
+    Ok(entries)
+}
+
+/// Project-relative paths with uncommitted changes. `None` when the project
+/// is not in a git work tree or its state cannot be read.
+pub fn dirty_paths(cfg: &ResolvedConfig) -> Option<BTreeSet<PathBuf>> {
+    let repo = Repo::discover(&cfg.orig_root)?;
+    let prefix = cfg.orig_root.strip_prefix(&repo.work_tree).ok()?;
+    match repo.dirty() {
+        Ok(dirty) => Some(
+            dirty
+                .into_iter()
+                .filter_map(|p| p.strip_prefix(prefix).ok().map(Path::to_path_buf))
+                .collect(),
+        ),
+        Err(e) => {
+            warn!("cannot tell whether the project is clean: {e:#}");
+            None
+        }
+    }
+}
+
+/// Enforces `git.require_clean`: fails listing the dirty paths among
+/// `candidates` (all dirty paths when `None`). `action` names what is being
+/// refused.
+pub fn ensure_clean(
+    cfg: &ResolvedConfig,
+    candidates: Option<&BTreeSet<PathBuf>>,
+    action: &str,
+) -> Result<()> {
+    if !cfg.raw.git.require_clean {
+        return Ok(());
+    }
+    let Some(dirty) = dirty_paths(cfg) else {
+        return Ok(());
+    };
+    let offending: Vec<&PathBuf> = dirty
+        .iter()
+        .filter(|p| candidates.is_none_or(|c| c.contains(*p)))
+        .collect();
+    if offending.is_empty() {
+        return Ok(());
+    }
+    let mut listing: Vec<String> = offending
+        .iter()
+        .take(LISTED)
+        .map(|p| format!("  {}", p.display()))
+        .collect();
+    if offending.len() > LISTED {
+        listing.push(format!("  ... and {} more", offending.len() - LISTED));
+    }
+    bail!(
+        "git.require_clean: {action}; {} files have uncommitted changes on disk:\n{}\n\
+         commit or stash them, or pass --ignore-dirty",
+        offending.len(),
+        listing.join("\n")
+    )
+}
+
+/// One path `stage_synced` put in the index, as `git diff --cached` sees it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StagedChange {
+    /// `A`, `M`, `D`, `T` or `R`.
+    pub status: char,
+    pub path: PathBuf,
+    /// The old path of a rename.
+    pub from: Option<PathBuf>,
+}
+
+impl std::fmt::Display for StagedChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.from {
+            Some(from) => write!(
+                f,
+                "{} {} -> {}",
+                self.status,
+                from.display(),
+                self.path.display()
+            ),
+            None => write!(f, "{} {}", self.status, self.path.display()),
+        }
+    }
+}
+
+/// Stages exactly `paths` (project-relative files a sync back wrote or
+/// deleted) and returns what ended up in the index for them. Whatever else
+/// the user had pending is left alone. `None` when the project is not in a
+/// git work tree or git is not installed.
+pub fn stage_synced(cfg: &ResolvedConfig, paths: &[PathBuf]) -> Result<Option<Vec<StagedChange>>> {
+    let Some(repo) = Repo::discover(&cfg.orig_root) else {
+        debug!(
+            "{} is not in a git work tree; nothing to stage",
+            cfg.orig_root.display()
+        );
+        return Ok(None);
+    };
+    let Some(git) = find_in_path("git") else {
+        warn!("git.auto_stage_synced needs git in PATH; nothing staged");
+        return Ok(None);
+    };
+    let prefix = cfg
+        .orig_root
+        .strip_prefix(&repo.work_tree)
+        .context("project root is outside its git work tree")?;
+    let mut present = Vec::new();
+    let mut gone = Vec::new();
+    for path in paths {
+        let in_repo = prefix.join(path);
+        if fs::symlink_metadata(cfg.orig_root.join(path)).is_ok() {
+            present.push(in_repo);
+        } else {
+            gone.push(in_repo);
+        }
+    }
+    // Ignored files stay out of the index, like with a plain `git add`.
+    let ignored = repo.run(&git, &["check-ignore", "-z", "--stdin"], &present, &[0, 1])?;
+    let ignored: BTreeSet<PathBuf> = split_paths(&ignored).collect();
+    present.retain(|p| !ignored.contains(p));
+    if !present.is_empty() {
+        repo.run(
+            &git,
+            &[
+                "--literal-pathspecs",
+                "add",
+                "--pathspec-from-file=-",
+                "--pathspec-file-nul",
+            ],
+            &present,
+            &[0],
+        )?;
+    }
+    if !gone.is_empty() {
+        repo.run(
+            &git,
+            &[
+                "--literal-pathspecs",
+                "rm",
+                "--cached",
+                "--quiet",
+                "--ignore-unmatch",
+                "--pathspec-from-file=-",
+                "--pathspec-file-nul",
+            ],
+            &gone,
+            &[0],
+        )?;
+    }
+
+    let ours: BTreeSet<&PathBuf> = present.iter().chain(&gone).collect();
+    let diff = repo.run(
+        &git,
+        &["diff", "--cached", "--name-status", "-M", "-z"],
+        &[],
+        &[0],
+    )?;
+    let mut staged = Vec::new();
+    let mut fields = split_paths(&diff);
+    while let Some(status) = fields.next() {
+        let status = status.to_string_lossy().chars().next().unwrap_or('M');
+        let Some(first) = fields.next() else { break };
+        let (path, from) = if matches!(status, 'R' | 'C') {
+            let Some(to) = fields.next() else { break };
+            (to, Some(first))
+        } else {
+            (first, None)
+        };
+        if !ours.contains(&path) {
+            continue;
+        }
+        let relative = |p: PathBuf| p.strip_prefix(prefix).map(Path::to_path_buf).unwrap_or(p);
+        staged.push(StagedChange {
+            status,
+            path: relative(path),
+            from: from.map(relative),
+        });
+    }
+    Ok(Some(staged))
+}
+
+fn split_paths(output: &[u8]) -> impl Iterator<Item = PathBuf> + '_ {
+    output
+        .split(|b| *b == 0)
+        .filter(|f| !f.is_empty())
+        .map(|f| PathBuf::from(std::ffi::OsStr::from_bytes(f)))
+}
+
+/// Stages what a sync back wrote or deleted when `git.auto_stage_synced` is
+/// set, and logs what was staged. Sync back has already succeeded by now, so
+/// failures are only reported.
+pub fn auto_stage(cfg: &ResolvedConfig, paths: &[PathBuf]) {
+    if !cfg.raw.git.auto_stage_synced || paths.is_empty() {
+        return;
+    }
+    match stage_synced(cfg, paths) {
+        Ok(Some(staged)) => {
+            info!("staged {} synced changes", staged.len());
+            for change in staged {
+                info!("  {change}");
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("failed to stage synced files: {e:#}"),
+    }
+}
+
+/// A source's `select` setting, bound to one sync root on disk and its
+/// mirror in RAM. Membership is evaluated on both sides, so every direction
+/// of sync agrees on which files belong to the source.
+#[derive(Debug, Clone)]
+pub struct Selector {
+    pub mode: SourceSelect,
+    pub disk: PathBuf,
+    pub ram: PathBuf,
+}
+
+impl Selector {
+    pub fn rebase(&self, sub: &Path) -> Self {
+        Selector {
+            mode: self.mode,
+            disk: native::target(&self.disk, sub),
+            ram: native::target(&self.ram, sub),
+        }
+    }
+
+    /// Lists the members below the sync root: the tracked files from the
+    /// index, plus untracked files git does not ignore, on disk (`git
+    /// ls-files --others`) and in RAM (`git check-ignore`). `patterns`
+    /// prunes the walk of the RAM side.
+    pub(crate) fn members(&self, patterns: &Filter) -> Result<Members> {
+        let Some(repo) = Repo::discover(&self.disk) else {
+            bail!(
+                "select: {} needs {} to be inside a git work tree",
+                self.mode,
+                self.disk.display()
+            );
+        };
+        let prefix: PathBuf = match self.disk.strip_prefix(&repo.work_tree) {
+            Ok(prefix) => prefix.components().collect(),
+            Err(_) => bail!("{} is outside its git work tree", self.disk.display()),
+        };
+        let strip = |path: &Path| path.strip_prefix(&prefix).ok().map(Path::to_path_buf);
+        let mut members = Members::default();
+        let index = repo.git_dir.join("index");
+        if index.exists() {
+            let data = fs::read(&index)?;
+            let entries = parse_index(&data)
+                .with_context(|| format!("failed to parse {}", index.display()))?;
+            for entry in entries {
+                let Some(rel) = strip(&entry.path) else {
+                    continue;
+                };
+                if entry.mode & 0o170000 == 0o160000 {
+                    members.insert_tree(rel);
+                } else {
+                    members.insert(rel);
+                }
+            }
+        }
+        if self.mode != SourceSelect::GitTrackedUntracked {
+            return Ok(members);
+        }
+        let Some(git) = find_in_path("git") else {
+            warn!(
+                "select: {} needs git in PATH; only tracked files are selected",
+                self.mode
+            );
+            return Ok(members);
+        };
+        let mut args = vec![
+            "--literal-pathspecs",
+            "ls-files",
+            "-z",
+            "--others",
+            "--exclude-standard",
+        ];
+        let pathspec = prefix.to_string_lossy();
+        if !pathspec.is_empty() {
+            args.extend(["--", &pathspec]);
+        }
+        let untracked = repo.run(&git, &args, &[], &[0])?;
+        for path in split_paths(&untracked) {
+            if let Some(rel) = strip(&path) {
+                members.insert(rel);
+            }
+        }
+
+        // Files that so far exist only in RAM.
+        let mut candidates = Vec::new();
+        if fs::symlink_metadata(&self.ram).is_ok() {
+            let walker = WalkDir::new(&self.ram)
+                .follow_links(false)
+                .into_iter()
+                .filter_entry(|e| {
+                    let rel = e.path().strip_prefix(&self.ram).unwrap_or(e.path());
+                    e.file_name() != ".git" && !patterns.is_excluded(rel, e.file_type().is_dir())
+                });
+            for item in walker {
+                let item = item?;
+                let rel = item.path().strip_prefix(&self.ram)?;
+                if !item.file_type().is_dir() && !members.contains(rel, false) {
+                    candidates.push(native::target(&prefix, rel));
+                }
+            }
+        }
+        if !candidates.is_empty() {
+            let ignored = repo.run(
+                &git,
+                &["check-ignore", "-z", "--stdin"],
+                &candidates,
+                &[0, 1],
+            )?;
+            let ignored: BTreeSet<PathBuf> = split_paths(&ignored).collect();
+            for path in candidates.iter().filter(|p| !ignored.contains(*p)) {
+                members.insert(strip(path).unwrap_or_default());
+            }
+        }
+        Ok(members)
+    }
+}
+
+/// Paths admitted by a `Selector`, relative to its sync root; the root itself
+/// is the empty path.
+#[derive(Debug, Default)]
+pub(crate) struct Members {
+    files: BTreeSet<PathBuf>,
+    dirs: BTreeSet<PathBuf>,
+    /// Submodules, admitted with everything below them.
+    trees: BTreeSet<PathBuf>,
+}
+
+impl Members {
+    fn insert(&mut self, rel: PathBuf) {
+        self.dirs
+            .extend(rel.ancestors().skip(1).map(Path::to_path_buf));
+        self.files.insert(rel);
+    }
+
+    fn insert_tree(&mut self, rel: PathBuf) {
+        self.dirs
+            .extend(rel.ancestors().skip(1).map(Path::to_path_buf));
+        self.trees.insert(rel);
+    }
+
+    pub(crate) fn contains(&self, rel: &Path, is_dir: bool) -> bool {
+        if rel.ancestors().any(|a| self.trees.contains(a)) {
+            return true;
+        }
+        if is_dir {
+            self.dirs.contains(rel)
+        } else {
+            self.files.contains(rel)
+        }
+    }
+}
+
+/// The project's repository for `git.mode: worktree`, which needs git and a
+/// project root that is the root of its work tree.
+fn worktree_repo(cfg: &ResolvedConfig) -> Result<(Repo, PathBuf)> {
+    let git = find_in_path("git").context("git.mode: worktree needs git in PATH")?;
+    let repo = Repo::discover(&cfg.orig_root)
+        .with_context(|| format!("{} is not in a git work tree", cfg.orig_root.display()))?;
+    let same = |a: &Path, b: &Path| fs::canonicalize(a).ok() == fs::canonicalize(b).ok();
+    if !same(&repo.work_tree, &cfg.orig_root) {
+        bail!(
+            "git.mode: worktree needs the project root to be the top of its work tree ({})",
+            repo.work_tree.display()
+        );
+    }
+    Ok((repo, git))
+}
+
+/// Registers the empty workspace root as a linked worktree of the project's
+/// repository, sharing its object store. Nothing is checked out: the files
+/// arrive through the normal mirror, and `reset_worktree_index` then loads
+/// the worktree's index from HEAD.
+pub fn add_worktree(cfg: &ResolvedConfig) -> Result<()> {
+    let ws = &cfg.workspace_root;
+    if ws.join(".git").exists() {
+        return Ok(());
+    }
+    if !is_empty_dir(ws)? {
+        warn!(
+            "{} already holds a workspace without git; git.mode: worktree applies from the \
+             next ramws destroy",
+            ws.display()
+        );
+        return Ok(());
+    }
+    let (repo, git) = worktree_repo(cfg)?;
+    // A worktree that vanished with its tmpfs is still registered, and git
+    // will not add it again until that entry is gone. Other stale worktrees
+    // of the project are none of our business.
+    if let Some(stale) = registered_worktree(&repo, &git, ws)? {
+        fs::remove_dir_all(&stale)
+            .with_context(|| format!("failed to remove {}", stale.display()))?;
+    }
+    let target = ws.to_string_lossy();
+    repo.run(
+        &git,
+        &[
+            "worktree",
+            "add",
+            "--quiet",
+            "--no-checkout",
+            "--detach",
+            &target,
+            "HEAD",
+        ],
+        &[],
+        &[0],
+    )?;
+    info!(
+        "registered {} as a linked worktree of {}",
+        ws.display(),
+        repo.work_tree.display()
+    );
+    Ok(())
+}
+
+/// Points the workspace worktree's index at HEAD without touching files, so
+/// `git status` in RAM shows the mirrored changes.
+pub fn reset_worktree_index(cfg: &ResolvedConfig) -> Result<()> {
+    let (_, git) = worktree_repo(cfg)?;
+    let worktree = Repo {
+        work_tree: cfg.workspace_root.clone(),
+        git_dir: cfg.workspace_root.join(".git"),
+    };
+    worktree.run(&git, &["reset", "--quiet"], &[], &[0])?;
+    Ok(())
+}
+
+/// The commit checked out in the workspace worktree when no branch, tag or
+/// other ref of the project contains it, i.e. commits made in RAM that
+/// pruning the worktree would leave unreachable.
+pub fn orphaned_worktree_head(cfg: &ResolvedConfig) -> Result<Option<String>> {
+    let ws = &cfg.workspace_root;
+    if !ws.join(".git").is_file() {
+        return Ok(None);
+    }
+    let (Some(repo), Some(git)) = (Repo::discover(&cfg.orig_root), find_in_path("git")) else {
+        return Ok(None);
+    };
+    let worktree = Repo {
+        work_tree: ws.clone(),
+        git_dir: ws.join(".git"),
+    };
+    let head = worktree.run(
+        &git,
+        &["rev-parse", "--verify", "--quiet", "HEAD"],
+        &[],
+        &[0, 1],
+    )?;
+    let head = String::from_utf8_lossy(&head).trim().to_string();
+    if head.is_empty() {
+        return Ok(None);
+    }
+    let refs = repo.run(
+        &git,
+        &["for-each-ref", "--count=1", "--contains", &head],
+        &[],
+        &[0],
+    )?;
+    // The project's own HEAD counts too, even when it is detached.
+    let base = repo.run(&git, &["merge-base", &head, "HEAD"], &[], &[0, 1])?;
+    if !refs.is_empty() || String::from_utf8_lossy(&base).trim() == head {
+        return Ok(None);
+    }
+    Ok(Some(head))
+}
+
+/// Drops the project's registration of the workspace worktree after
+/// `destroy` removed the workspace. Falls back to deleting just its entry
+/// when git will not remove it.
+pub fn remove_worktree(cfg: &ResolvedConfig) -> Result<()> {
+    let (Some(repo), Some(git)) = (Repo::discover(&cfg.orig_root), find_in_path("git")) else {
+        return Ok(());
+    };
+    let ws = &cfg.workspace_root;
+    let target = ws.to_string_lossy();
+    if let Err(e) = repo.run(&git, &["worktree", "remove", "--force", &target], &[], &[0]) {
+        debug!("git worktree remove failed: {e:#}");
+        if let Some(entry) = registered_worktree(&repo, &git, ws)? {
+            fs::remove_dir_all(&entry)
+                .with_context(|| format!("failed to remove {}", entry.display()))?;
+        }
+    }
+    info!(
+        "removed the workspace worktree from {}",
+        repo.work_tree.display()
+    );
+    Ok(())
+}
+
+/// The entry under the repository's `worktrees` dir that registers `ws`,
+/// found by its `gitdir` file pointing at `ws/.git`.
+fn registered_worktree(repo: &Repo, git: &Path, ws: &Path) -> Result<Option<PathBuf>> {
+    let common = repo.run(git, &["rev-parse", "--git-common-dir"], &[], &[0])?;
+    let worktrees = repo
+        .work_tree
+        .join(String::from_utf8_lossy(&common).trim())
+        .join("worktrees");
+    // git records the real path, and `ws` itself may already be gone.
+    let ws = fs::canonicalize(ws)
+        .ok()
+        .or_else(|| {
+            let parent = fs::canonicalize(ws.parent()?).ok()?;
+            Some(parent.join(ws.file_name()?))
+        })
+        .unwrap_or_else(|| ws.to_path_buf());
+    let ours = ws.join(".git");
+    let entries = match fs::read_dir(&worktrees) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", worktrees.display())),
+    };
+    for entry in entries {
+        let entry = entry?.path();
+        let Ok(gitdir) = fs::read_to_string(entry.join("gitdir")) else {
+            continue;
+        };
+        if Path::new(gitdir.trim()) == ours {
+            return Ok(Some(entry));
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,136 @@
+use crate::git::Repo;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+const GITIGNORE: &str = ".gitignore";
+const RAMWSIGNORE: &str = ".ramwsignore";
+
+/// Where a sync finds its gitignore-syntax ignore files. Rules are always
+/// read from the project on disk, so both directions of sync agree.
+#[derive(Debug, Clone)]
+pub struct IgnoreFiles {
+    /// The sync root on disk.
+    pub disk: PathBuf,
+    /// The project root; ignore files above it are only read when it is
+    /// inside a git work tree.
+    pub project: PathBuf,
+    /// Read git's own ignore files too, not just `.ramwsignore`. Off for
+    /// sources whose `select` already asks git.
+    pub git: bool,
+}
+
+impl IgnoreFiles {
+    pub fn rebase(&self, sub: &Path) -> Self {
+        IgnoreFiles {
+            disk: crate::native::target(&self.disk, sub),
+            project: self.project.clone(),
+            git: self.git,
+        }
+    }
+
+    pub fn matcher(&self) -> IgnoreMatcher {
+        let repo = Repo::discover(&self.project);
+        let top: PathBuf = repo
+            .as_ref()
+            .map_or(self.project.as_path(), |r| r.work_tree.as_path())
+            .components()
+            .collect();
+        let mut fixed = Vec::new();
+        if self.git {
+            if let Some(repo) = &repo {
+                fixed.extend(load(&top, &[repo.git_dir.join("info/exclude")]));
+            }
+            let (global, err) = GitignoreBuilder::new(&top).build_global();
+            if let Some(e) = err {
+                warn!("failed to read the global git excludes file: {e}");
+            }
+            if !global.is_empty() {
+                fixed.push(Arc::new(global));
+            }
+        }
+        let names = if self.git {
+            vec![GITIGNORE, RAMWSIGNORE]
+        } else {
+            vec![RAMWSIGNORE]
+        };
+        IgnoreMatcher {
+            // `Gitignore` strips its root textually, so drop any `.` parts.
+            root: self.disk.components().collect(),
+            top,
+            names,
+            fixed,
+            dirs: Mutex::default(),
+        }
+    }
+}
+
+/// Answers whether a path below the sync root is ignored, with git's
+/// precedence: the deepest directory's ignore files first, then
+/// `.git/info/exclude`, then the global excludes file. Directory matchers are
+/// loaded the first time a path below them is checked.
+pub struct IgnoreMatcher {
+    root: PathBuf,
+    top: PathBuf,
+    /// Per-directory ignore files, read in this order so the later one wins.
+    names: Vec<&'static str>,
+    fixed: Vec<Arc<Gitignore>>,
+    dirs: Mutex<HashMap<PathBuf, Option<Arc<Gitignore>>>>,
+}
+
+impl IgnoreMatcher {
+    /// `rel` is relative to the sync root. Only the path itself is checked;
+    /// callers walking a tree prune ignored directories.
+    pub fn is_ignored(&self, rel: &Path, is_dir: bool) -> bool {
+        let path = crate::native::target(&self.root, rel);
+        let mut dir = path.parent();
+        while let Some(current) = dir.filter(|d| d.starts_with(&self.top)) {
+            if let Some(matcher) = self.dir_matcher(current) {
+                match matcher.matched(&path, is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => {}
+                }
+            }
+            dir = current.parent();
+        }
+        self.fixed
+            .iter()
+            .find_map(|m| match m.matched(&path, is_dir) {
+                Match::Ignore(_) => Some(true),
+                Match::Whitelist(_) => Some(false),
+                Match::None => None,
+            })
+            .unwrap_or(false)
+    }
+
+    fn dir_matcher(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+        let mut dirs = self.dirs.lock().unwrap_or_else(|e| e.into_inner());
+        dirs.entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let files: Vec<PathBuf> = self.names.iter().map(|name| dir.join(name)).collect();
+                load(dir, &files)
+            })
+            .clone()
+    }
+}
+
+fn load(root: &Path, files: &[PathBuf]) -> Option<Arc<Gitignore>> {
+    let mut builder = GitignoreBuilder::new(root);
+    for file in files.iter().filter(|f| f.is_file()) {
+        if let Some(e) = builder.add(file) {
+            warn!("ignoring bad rules in {}: {e}", file.display());
+        }
+    }
+    match builder.build() {
+        Ok(matcher) if !matcher.is_empty() => Some(Arc::new(matcher)),
+        Ok(_) => None,
+        Err(e) => {
+            warn!("failed to load ignore files in {}: {e}", root.display());
+            None
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use crate::backup::Backup;
+use crate::config::ResolvedConfig;
+use crate::manifest::Manifest;
+use crate::native::{self, NativeBackend};
+use crate::syncer::{ChangeKind, FileChange, FileType, SyncBackend, SyncOptions};
+use crate::util::{ensure_dir, join_relative};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Changes to one synced path; change paths are relative to `rel`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JournalPlan {
+    pub rel: PathBuf,
+    pub changes: Vec<FileChange>,
+}
+
+/// Written before sync back touches disk and removed once it is done. If
+/// ramws finds one at startup the previous sync was interrupted.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Journal {
+    /// Frozen RAM copy the changes are applied from.
+    pub staging: PathBuf,
+    /// Backup holding the disk state before the sync, if one was taken.
+    pub backup: Option<String>,
+    pub plans: Vec<JournalPlan>,
+}
+
+impl Journal {
+    pub fn location(cfg: &ResolvedConfig) -> PathBuf {
+        cfg.state_dir().join("journal.json")
+    }
+
+    pub fn load(cfg: &ResolvedConfig) -> Result<Option<Self>> {
+        let path = Self::location(cfg);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        let journal = serde_json::from_str(&text)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        Ok(Some(journal))
+    }
+
+    /// Durably records the journal; only then may the changes be applied.
+    pub fn begin(&self, cfg: &ResolvedConfig) -> Result<()> {
+        ensure_dir(&cfg.state_dir())?;
+        let path = Self::location(cfg);
+        let tmp = native::tmp_path(&path);
+        let mut file =
+            File::create(&tmp).with_context(|| format!("failed to write {}", tmp.display()))?;
+        file.write_all(&serde_json::to_vec(self)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path).with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Marks the sync as complete.
+    pub fn finish(cfg: &ResolvedConfig) -> Result<()> {
+        let path = Self::location(cfg);
+        fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))
+    }
+
+    /// Re-applies every change from staging. Writes and deletions are
+    /// idempotent, so changes that already landed are simply repeated.
+    pub fn apply(&self, cfg: &ResolvedConfig) -> Result<Vec<PathBuf>> {
+        let opts = SyncOptions::from_config(&cfg.raw.sync);
+        let mut written = Vec::new();
+        for plan in &self.plans {
+            let source = self.staging.join(&plan.rel);
+            let dest = cfg.orig_root.join(&plan.rel);
+            NativeBackend.apply(&source, &dest, &plan.changes, &opts)?;
+            written.extend(
+                plan.changes
+                    .iter()
+                    .filter(|c| c.file_type != FileType::Dir)
+                    .map(|c| join_relative(&plan.rel, &c.path)),
+            );
+        }
+        Ok(written)
+    }
+
+    /// Whether staging still holds everything needed to roll forward.
+    fn staging_complete(&self) -> bool {
+        self.plans.iter().all(|plan| {
+            let root = self.staging.join(&plan.rel);
+            plan.changes.iter().all(|c| {
+                c.kind == ChangeKind::Deleted
+                    || fs::symlink_metadata(native::target(&root, &c.path)).is_ok()
+            })
+        })
+    }
+
+    fn remove_temp_files(&self, cfg: &ResolvedConfig) -> Result<()> {
+        for plan in &self.plans {
+            let dest = cfg.orig_root.join(&plan.rel);
+            for change in &plan.changes {
+                let path = native::target(&dest, &change.path);
+                if path.parent().is_some_and(Path::is_dir) {
+                    native::remove_tmp(&native::tmp_path(&path))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Finishes or undoes a sync back that was interrupted: rolls forward from
+/// staging when it survived, otherwise rolls back from the pre-sync backup.
+pub fn recover(cfg: &ResolvedConfig) -> Result<()> {
+    let Some(journal) = Journal::load(cfg)? else {
+        return Ok(());
+    };
+    warn!("previous sync back was interrupted; recovering");
+    journal.remove_temp_files(cfg)?;
+    let forward = if journal.staging_complete() {
+        match journal.apply(cfg) {
+            Ok(written) => {
+                if let Some(mut baseline) = Manifest::load(cfg)? {
+                    baseline.update_from(&cfg.orig_root, &written)?;
+                    baseline.save(cfg)?;
+                }
+                info!("rolled forward {} files from staging", written.len());
+                true
+            }
+            Err(e) => {
+                warn!("rolling forward failed: {e:#}");
+                false
+            }
+        }
+    } else {
+        false
+    };
+    if !forward {
+        match &journal.backup {
+            Some(id) => {
+                Backup::find(cfg, Some(id))?.restore(cfg)?;
+                info!("rolled back to the disk state saved in backup {id}");
+            }
+            None => warn!(
+                "staging is gone and no backup was taken; the project may be partially synced"
+            ),
+        }
+    }
+    if journal.staging.exists() {
+        fs::remove_dir_all(&journal.staging).ok();
+    }
+    Journal::finish(cfg)
+}
@@ -0,0 +1,129 @@
+use crate::config::ResolvedConfig;
+use crate::manifest::Manifest;
+use crate::native;
+use crate::syncer::{backend_for, source_options, FileType};
+use crate::util::{ensure_dir, join_relative};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Files a lazy start left on disk, by project-relative path. They are absent
+/// from RAM because they were never copied, not because they were deleted.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct LazySet {
+    pub pending: BTreeSet<PathBuf>,
+}
+
+impl LazySet {
+    pub fn location(cfg: &ResolvedConfig) -> PathBuf {
+        cfg.state_dir().join("lazy.json")
+    }
+
+    pub fn load(cfg: &ResolvedConfig) -> Result<Self> {
+        let path = Self::location(cfg);
+        match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("failed to parse {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    /// Writes the set, or removes the file once everything is in RAM.
+    pub fn save(&self, cfg: &ResolvedConfig) -> Result<()> {
+        let path = Self::location(cfg);
+        if self.pending.is_empty() {
+            return match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    Err(e).with_context(|| format!("failed to remove {}", path.display()))
+                }
+                _ => Ok(()),
+            };
+        }
+        ensure_dir(&cfg.state_dir())?;
+        let tmp = native::tmp_path(&path);
+        fs::write(&tmp, serde_json::to_vec(self)?)
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &path).with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Loads the set, dropping paths that have since been created in RAM so
+    /// deleting them there counts again. Only reads; commands that write to
+    /// the project save the result so the drop sticks.
+    pub fn current(cfg: &ResolvedConfig) -> Result<Self> {
+        let mut lazy = Self::load(cfg)?;
+        lazy.pending
+            .retain(|p| fs::symlink_metadata(cfg.workspace_root.join(p)).is_err());
+        Ok(lazy)
+    }
+
+    pub fn is_pending(&self, path: &Path) -> bool {
+        self.pending.contains(path)
+    }
+}
+
+/// Creates the directory skeleton of every source in RAM and copies symlinks
+/// and files matching the source's `hot` patterns. Everything else is
+/// returned as pending.
+pub fn populate(cfg: &ResolvedConfig) -> Result<LazySet> {
+    let backend = backend_for(cfg.raw.sync.backend);
+    let mut lazy = LazySet::default();
+    let mut copied = 0;
+    for source in &cfg.raw.sources {
+        let opts = source_options(cfg, source);
+        let hot = native::glob_set(&source.hot)?;
+        let src = cfg.orig_root.join(&source.path);
+        let dest = cfg.workspace_root.join(&source.path);
+        ensure_dir(&dest)?;
+        let (now, later): (Vec<_>, Vec<_>) = backend
+            .plan(&src, &dest, &opts)?
+            .into_iter()
+            .partition(|c| c.file_type != FileType::File || hot.is_match(&c.path));
+        copied += now.iter().filter(|c| c.file_type == FileType::File).count();
+        backend.apply(&src, &dest, &now, &opts)?;
+        lazy.pending
+            .extend(later.iter().map(|c| join_relative(&source.path, &c.path)));
+    }
+    info!("lazy start: copied {copied} hot files");
+    if !lazy.pending.is_empty() {
+        eprintln!(
+            "ramws: lazy start left {} files on disk; they are missing from RAM until \
+             ramws fetch copies them in (workspace.mode: overlay reads them on access instead)",
+            lazy.pending.len()
+        );
+    }
+    Ok(lazy)
+}
+
+/// Copies pending files under the project-relative `paths` (all of them when
+/// empty) into RAM and adds them to the baseline. Returns what was fetched.
+pub fn fetch(cfg: &ResolvedConfig, paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut lazy = LazySet::current(cfg)?;
+    let selected: Vec<PathBuf> = paths
+        .iter()
+        .map(|p| join_relative(Path::new(""), p))
+        .collect();
+    let wanted: Vec<PathBuf> = lazy
+        .pending
+        .iter()
+        .filter(|p| selected.is_empty() || selected.iter().any(|s| p.starts_with(s)))
+        .cloned()
+        .collect();
+    let mut fetched = Vec::new();
+    for path in wanted {
+        lazy.pending.remove(&path);
+        if native::copy_path(&cfg.orig_root.join(&path), &cfg.workspace_root.join(&path))? {
+            fetched.push(path);
+        }
+    }
+    if let Some(mut baseline) = Manifest::load(cfg)? {
+        baseline.update_from(&cfg.workspace_root, &fetched)?;
+        baseline.save(cfg)?;
+    }
+    lazy.save(cfg)?;
+    Ok(fetched)
+}
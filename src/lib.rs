@@ -1,6 +1,21 @@
+pub mod backup;
+pub mod budget;
+pub mod checkpoint;
 pub mod config;
+pub mod conflict;
+pub mod git;
+pub mod ignore_files;
+pub mod journal;
+pub mod lazy;
+pub mod manifest;
+pub mod mount;
+pub mod native;
+pub mod overlay;
+pub mod patch;
+pub mod pressure;
 pub mod shell;
 pub mod status;
 pub mod syncer;
 pub mod util;
+pub mod watch;
 pub mod workspace;
@@ -1,18 +1,19 @@
-mod config;
-mod shell;
-mod status;
-mod syncer;
-mod util;
-mod workspace;
-
-use crate::config::{BuildDirType, Config, ResolvedConfig, SyncOnExit};
-use crate::shell::{run_shell, ShellOptions};
-use crate::status::collect_status;
-use crate::syncer::{refresh_from_orig, sync_back};
-use crate::util::find_project_root;
-use crate::workspace::Workspace;
 use anyhow::{bail, Context, Result};
 use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use ramws::backup::{self, Backup};
+use ramws::checkpoint::Checkpoint;
+use ramws::config::{BuildDirType, Config, ResolvedConfig, SyncOnExit};
+use ramws::patch::render_patch;
+use ramws::pressure::BackgroundPressure;
+use ramws::shell::{run_shell, ShellOptions};
+use ramws::status::collect_status;
+use ramws::syncer::{
+    pending_changes, refresh_from_orig, sync_back, ChangeKind, DiffSummary, FileChange,
+    SyncBackOptions,
+};
+use ramws::util::{find_project_root, format_bytes, project_relative};
+use ramws::watch::{stop_on_signals, watch, Direction};
+use ramws::workspace::{StartOptions, Workspace};
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -30,7 +31,7 @@ struct Cli {
     chdir: Option<PathBuf>,
     #[arg(long)]
     config: Option<PathBuf>,
-    #[arg(long)]
+    #[arg(long, global = true)]
     json: bool,
     #[arg(short, long, action = ArgAction::Count)]
     verbose: u8,
@@ -53,6 +54,16 @@ enum Commands {
         noninteractive: bool,
         #[arg(long)]
         refresh_sources_only: bool,
+        /// Rehydrate the workspace from the latest checkpoint.
+        #[arg(long, conflicts_with = "refresh_sources_only")]
+        from_checkpoint: bool,
+        /// Copy only the directory skeleton and each source's `hot` files;
+        /// the rest is missing from RAM until `ramws fetch` copies it in.
+        #[arg(long, conflicts_with = "from_checkpoint")]
+        lazy: bool,
+        /// Start even when `git.require_clean` finds uncommitted changes.
+        #[arg(long)]
+        ignore_dirty: bool,
     },
     Shell {
         #[arg(long)]
@@ -61,6 +72,9 @@ enum Commands {
         no_prompt: bool,
         #[arg(long)]
         noninteractive: bool,
+        /// Ignore `git.require_clean` when starting and when syncing on exit.
+        #[arg(long)]
+        ignore_dirty: bool,
         #[arg(trailing_var_arg = true)]
         command: Vec<String>,
     },
@@ -75,14 +89,61 @@ enum Commands {
         roles: Vec<Role>,
         #[arg(long)]
         noninteractive: bool,
+        #[arg(long)]
+        allow_mass_delete: bool,
+        /// Overwrite files with uncommitted changes despite
+        /// `git.require_clean`.
+        #[arg(long)]
+        ignore_dirty: bool,
     },
     Status {},
+    Diff {
+        #[arg(long, conflicts_with = "patch")]
+        stat: bool,
+        #[arg(long)]
+        patch: bool,
+        #[arg(value_name = "PATH")]
+        paths: Vec<PathBuf>,
+    },
     Destroy {
         #[arg(long)]
         force: bool,
         #[arg(long)]
         noninteractive: bool,
     },
+    /// Copy files a lazy start left on disk into RAM.
+    Fetch {
+        /// Project-relative paths to fetch below; everything when omitted.
+        paths: Vec<PathBuf>,
+    },
+    /// Snapshot the whole workspace (changed files only) to persistent storage.
+    Checkpoint {},
+    /// Push source edits in RAM back to disk as they happen, or with
+    /// `--from` pull disk-side edits into RAM.
+    Watch {
+        #[arg(long)]
+        from: bool,
+    },
+    /// Restore the disk state saved before a sync back (the latest by default).
+    Undo {
+        id: Option<String>,
+        #[arg(long)]
+        noninteractive: bool,
+    },
+    Backups {
+        #[command(subcommand)]
+        action: BackupsAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum BackupsAction {
+    List,
+    /// Remove backups outside the configured retention.
+    Prune {
+        #[arg(long)]
+        keep: Option<usize>,
+    },
 }
 
 #[derive(Copy, Clone, Debug, ValueEnum, PartialEq, Eq)]
@@ -110,17 +171,31 @@ fn main() -> Result<()> {
         Commands::Start {
             noninteractive,
             refresh_sources_only,
-        } => start_command(&cli, *noninteractive, *refresh_sources_only),
+            from_checkpoint,
+            lazy,
+            ignore_dirty,
+        } => start_command(
+            &cli,
+            *noninteractive,
+            StartOptions {
+                refresh_sources_only: *refresh_sources_only,
+                lazy: *lazy,
+                ignore_dirty: *ignore_dirty,
+                from_checkpoint: *from_checkpoint,
+            },
+        ),
         Commands::Shell {
             shell,
             no_prompt,
             noninteractive,
+            ignore_dirty,
             command,
         } => shell_command(
             &cli,
             shell.clone(),
             *no_prompt,
             *noninteractive,
+            *ignore_dirty,
             command.clone(),
         ),
         Commands::Sync {
@@ -129,12 +204,31 @@ fn main() -> Result<()> {
             only,
             roles,
             noninteractive,
-        } => sync_command(&cli, !from, only.clone(), roles.clone(), *noninteractive),
+            allow_mass_delete,
+            ignore_dirty,
+        } => sync_command(
+            &cli,
+            !from,
+            only.clone(),
+            roles.clone(),
+            SyncBackOptions {
+                noninteractive: *noninteractive,
+                allow_mass_delete: *allow_mass_delete,
+                ignore_dirty: *ignore_dirty,
+                ..SyncBackOptions::default()
+            },
+        ),
         Commands::Status {} => status_command(&cli),
+        Commands::Diff { stat, patch, paths } => diff_command(&cli, *stat, *patch, paths),
         Commands::Destroy {
             force,
             noninteractive,
         } => destroy_command(&cli, *force, *noninteractive),
+        Commands::Fetch { paths } => fetch_command(&cli, paths),
+        Commands::Checkpoint {} => checkpoint_command(&cli),
+        Commands::Watch { from } => watch_command(&cli, *from),
+        Commands::Undo { id, noninteractive } => undo_command(&cli, id.as_deref(), *noninteractive),
+        Commands::Backups { action } => backups_command(&cli, action),
     }
 }
 
@@ -161,11 +255,16 @@ fn init_command(cli: &Cli, force: bool, template: Option<&str>) -> Result<()> {
     Ok(())
 }
 
+/// The directory commands run in: `--chdir` when given, else the cwd.
+fn working_dir(cli: &Cli) -> Result<PathBuf> {
+    match &cli.chdir {
+        Some(dir) => Ok(dir.clone()),
+        None => env::current_dir().context("failed to read cwd"),
+    }
+}
+
 fn load_resolved_config(cli: &Cli) -> Result<ResolvedConfig> {
-    let base = cli
-        .chdir
-        .clone()
-        .unwrap_or(env::current_dir().context("failed to read cwd")?);
+    let base = working_dir(cli)?;
     let orig_root = find_project_root(&base)?;
     let cfg_path = if let Some(p) = &cli.config {
         p.clone()
@@ -175,6 +274,14 @@ fn load_resolved_config(cli: &Cli) -> Result<ResolvedConfig> {
     Config::load_from_file(&cfg_path, orig_root)
 }
 
+/// Like `load_resolved_config`, for commands that write to the project:
+/// an interrupted sync back is finished or undone first.
+fn load_config_for_writing(cli: &Cli) -> Result<ResolvedConfig> {
+    let cfg = load_resolved_config(cli)?;
+    ramws::journal::recover(&cfg)?;
+    Ok(cfg)
+}
+
 fn discover_config(root: &Path) -> Result<PathBuf> {
     let mut current = root.to_path_buf();
     loop {
@@ -190,10 +297,10 @@ fn discover_config(root: &Path) -> Result<PathBuf> {
     }
 }
 
-fn start_command(cli: &Cli, _noninteractive: bool, refresh_sources_only: bool) -> Result<()> {
-    let cfg = load_resolved_config(cli)?;
+fn start_command(cli: &Cli, _noninteractive: bool, opts: StartOptions) -> Result<()> {
+    let cfg = load_config_for_writing(cli)?;
     let workspace = Workspace::new(cfg);
-    workspace.ensure(refresh_sources_only)?;
+    workspace.ensure(&opts)?;
     println!(
         "workspace ready at {}",
         workspace.config.workspace_root.display()
@@ -206,9 +313,10 @@ fn shell_command(
     shell: Option<String>,
     no_prompt: bool,
     noninteractive: bool,
+    ignore_dirty: bool,
     command: Vec<String>,
 ) -> Result<()> {
-    let cfg = load_resolved_config(cli)?;
+    let cfg = load_config_for_writing(cli)?;
     let workspace = Workspace::new(cfg.clone());
     let code = run_shell(
         &workspace,
@@ -217,44 +325,44 @@ fn shell_command(
             no_prompt,
             noninteractive,
             command,
+            ignore_dirty,
         },
     )?;
-    handle_on_exit(&cfg, noninteractive)?;
+    handle_on_exit(&cfg, noninteractive, ignore_dirty)?;
     std::process::exit(code);
 }
 
-fn handle_on_exit(cfg: &ResolvedConfig, noninteractive: bool) -> Result<()> {
+fn handle_on_exit(cfg: &ResolvedConfig, noninteractive: bool, ignore_dirty: bool) -> Result<()> {
     match cfg.raw.sync.on_exit {
         SyncOnExit::Never => Ok(()),
         SyncOnExit::Auto => {
             let paths: Vec<PathBuf> = cfg.raw.sources.iter().map(|s| s.path.clone()).collect();
-            sync_back(cfg, &paths, true)
+            let opts = SyncBackOptions {
+                noninteractive: true,
+                ignore_dirty,
+                ..SyncBackOptions::default()
+            };
+            sync_back(cfg, &paths, &opts)?;
+            Workspace::new(cfg.clone()).save_caches()
         }
         SyncOnExit::Ask => {
-            let paths: Vec<PathBuf> = cfg.raw.sources.iter().map(|s| s.path.clone()).collect();
-            let mut pending = false;
-            for rel in &paths {
-                let ws = cfg.workspace_root.join(rel);
-                let orig = cfg.orig_root.join(rel);
-                let opts = crate::syncer::SyncOptions {
-                    delete: cfg.raw.sync.delete,
-                    include: vec![],
-                    exclude: vec![],
-                    itemize: true,
-                    dry_run: true,
-                };
-                let diff = crate::syncer::diff_path(&ws, &orig, opts)?;
-                if diff.added + diff.changed + diff.deleted > 0 {
-                    pending = true;
-                    break;
-                }
+            let summary = DiffSummary::from_changes(&pending_changes(cfg)?);
+            if summary.added + summary.changed + summary.deleted == 0 {
+                return Ok(());
             }
-            if pending {
-                if crate::syncer::confirm_if_needed("Sync changes back to disk?", noninteractive)? {
-                    sync_back(cfg, &paths, noninteractive)
-                } else {
-                    Ok(())
-                }
+            let prompt = format!(
+                "Sync changes back to disk ({} changed, {} added, {} deleted)?",
+                summary.changed, summary.added, summary.deleted
+            );
+            if ramws::syncer::confirm_if_needed(&prompt, noninteractive)? {
+                let paths: Vec<PathBuf> = cfg.raw.sources.iter().map(|s| s.path.clone()).collect();
+                let opts = SyncBackOptions {
+                    noninteractive,
+                    ignore_dirty,
+                    ..SyncBackOptions::default()
+                };
+                sync_back(cfg, &paths, &opts)?;
+                Workspace::new(cfg.clone()).save_caches()
             } else {
                 Ok(())
             }
@@ -267,10 +375,11 @@ fn sync_command(
     back: bool,
     only: Vec<PathBuf>,
     roles: Vec<Role>,
-    noninteractive: bool,
+    back_opts: SyncBackOptions,
 ) -> Result<()> {
-    let cfg = load_resolved_config(cli)?;
+    let cfg = load_config_for_writing(cli)?;
     let include_sources = roles.is_empty() || roles.contains(&Role::Source);
+    let default_scope = only.is_empty() && roles.is_empty();
     let mut selected: Vec<PathBuf> = if !only.is_empty() {
         only
     } else {
@@ -299,10 +408,34 @@ fn sync_command(
     if selected.is_empty() {
         selected = cfg.raw.sources.iter().map(|s| s.path.clone()).collect();
     }
+    // Cache build dirs go to their persistent store rather than through the
+    // source sync.
+    let workspace = Workspace::new(cfg.clone());
+    let cache_paths: Vec<PathBuf> = cfg
+        .raw
+        .build_dirs
+        .iter()
+        .filter(|b| b.r#type == BuildDirType::Cache)
+        .map(|b| b.path.clone())
+        .collect();
+    let explicit_caches = selected.iter().any(|p| cache_paths.contains(p));
+    selected.retain(|p| !cache_paths.contains(p));
     if back {
-        sync_back(&cfg, &selected, noninteractive)
+        if !selected.is_empty() {
+            sync_back(&cfg, &selected, &back_opts)?;
+        }
+        if default_scope || explicit_caches {
+            workspace.save_caches()?;
+        }
+        Ok(())
     } else {
-        refresh_from_orig(&cfg, &selected)
+        if !selected.is_empty() {
+            refresh_from_orig(&cfg, &selected)?;
+        }
+        if explicit_caches {
+            workspace.seed_caches(true)?;
+        }
+        Ok(())
     }
 }
 
@@ -317,6 +450,20 @@ fn status_command(cli: &Cli) -> Result<()> {
         if let Some(fs) = report.fs_type {
             println!("Filesystem: {fs}");
         }
+        match &report.mount {
+            Some(mount) if mount.mounted => {
+                println!(
+                    "Mount: private tmpfs (size {}, nr_inodes {})",
+                    mount.size.as_deref().unwrap_or("default"),
+                    mount.nr_inodes.as_deref().unwrap_or("default")
+                );
+                if let (Some(used), Some(total)) = (mount.inodes_used, mount.inodes_total) {
+                    println!("Inodes: {used} of {total} used");
+                }
+            }
+            Some(_) => println!("Mount: not mounted; figures below are for the shared filesystem"),
+            None => {}
+        }
         if let Some(total) = report.total {
             println!(
                 "Capacity: total {total}, used {}",
@@ -332,10 +479,94 @@ fn status_command(cli: &Cli) -> Result<()> {
             report.diff_changed, report.diff_added, report.diff_deleted
         );
         println!("Sync on exit: {:?}", report.sync_policy);
+        if report.lazy_pending > 0 {
+            println!(
+                "Lazy: {} files still on disk only (ramws fetch)",
+                report.lazy_pending
+            );
+        }
+        for cache in &report.caches {
+            println!(
+                "Cache {}: {} in RAM, {} in {}",
+                cache.path,
+                format_bytes(cache.ram_bytes),
+                format_bytes(cache.store_bytes),
+                cache.store
+            );
+        }
+        for warning in &report.warnings {
+            println!("Warning: {warning}");
+        }
+    }
+    Ok(())
+}
+
+fn diff_command(cli: &Cli, stat: bool, patch: bool, paths: &[PathBuf]) -> Result<()> {
+    let cfg = load_resolved_config(cli)?;
+    if !cfg.workspace_root.exists() {
+        bail!(
+            "workspace not found at {}; run ramws start",
+            cfg.workspace_root.display()
+        );
+    }
+    let cwd = working_dir(cli)?;
+    let paths = paths
+        .iter()
+        .map(|p| project_relative(&cfg.orig_root, &cwd, p))
+        .collect::<Result<Vec<_>>>()?;
+    let changes: Vec<FileChange> = pending_changes(&cfg)?
+        .into_iter()
+        .filter(|c| paths.is_empty() || paths.iter().any(|p| c.path.starts_with(p)))
+        .collect();
+    if patch {
+        print!("{}", render_patch(&cfg, &changes)?);
+        return Ok(());
+    }
+    let summary = DiffSummary::from_changes(&changes);
+    let size_delta: i64 = changes.iter().map(|c| c.size_delta).sum();
+    if cli.json {
+        let value = if stat {
+            serde_json::json!({ "summary": summary, "size_delta": size_delta })
+        } else {
+            serde_json::to_value(&changes)?
+        };
+        println!("{}", serde_json::to_string_pretty(&value)?);
+        return Ok(());
+    }
+    for change in &changes {
+        let marker = match change.kind {
+            ChangeKind::Added => 'A',
+            ChangeKind::Modified => 'M',
+            ChangeKind::Deleted => 'D',
+        };
+        if stat {
+            println!(
+                " {marker} {} | {}",
+                change.path.display(),
+                format_signed_bytes(change.size_delta)
+            );
+        } else {
+            println!("{marker} {}", change.path.display());
+        }
+    }
+    if stat {
+        println!(
+            "{} files changed: {} added, {} modified, {} deleted ({})",
+            changes.len(),
+            summary.added,
+            summary.changed,
+            summary.deleted,
+            format_signed_bytes(size_delta)
+        );
     }
     Ok(())
 }
 
+fn format_signed_bytes(delta: i64) -> String {
+    let sign = if delta < 0 { '-' } else { '+' };
+    format!("{sign}{}", format_bytes(delta.unsigned_abs()))
+}
+
 fn destroy_command(cli: &Cli, force: bool, noninteractive: bool) -> Result<()> {
     let cfg = load_resolved_config(cli)?;
     let workspace = Workspace::new(cfg.clone());
@@ -346,16 +577,126 @@ fn destroy_command(cli: &Cli, force: bool, noninteractive: bool) -> Result<()> {
         );
         return Ok(());
     }
+    ramws::overlay::ensure_no_session(&cfg)?;
+    if let Some(head) = ramws::git::orphaned_worktree_head(&cfg)? {
+        if !force {
+            bail!(
+                "the workspace worktree is at commit {head}, which no branch or tag contains, \
+                 so destroy would lose it; create a branch for it (git -C {} branch <name>) \
+                 or pass --force",
+                cfg.workspace_root.display()
+            );
+        }
+        eprintln!(
+            "Warning: discarding commit {head} of the workspace worktree; no branch or tag \
+             contains it"
+        );
+    }
     if !force {
         let report = collect_status(&cfg)?;
-        if report.diff_added + report.diff_changed + report.diff_deleted > 0 {
-            if !crate::syncer::confirm_if_needed(
+        let unsynced = report.diff_added + report.diff_changed + report.diff_deleted;
+        if unsynced > 0 && ramws::overlay::is_unmounted(&cfg) {
+            bail!(
+                "the overlay at {} is not mounted and its upper layer holds {unsynced} unsynced \
+                 changes; run ramws sync --back first, or pass --force to discard them",
+                cfg.workspace_root.display()
+            );
+        }
+        if unsynced > 0
+            && !ramws::syncer::confirm_if_needed(
                 "Unsynced changes detected. Delete workspace?",
                 noninteractive,
-            )? {
-                return Ok(());
-            }
+            )?
+        {
+            return Ok(());
         }
     }
+    workspace.save_caches()?;
     workspace.delete()
 }
+
+fn fetch_command(cli: &Cli, paths: &[PathBuf]) -> Result<()> {
+    let cfg = load_resolved_config(cli)?;
+    let fetched = ramws::lazy::fetch(&cfg, paths)?;
+    println!("fetched {} files into RAM", fetched.len());
+    Ok(())
+}
+
+fn checkpoint_command(cli: &Cli) -> Result<()> {
+    let cfg = load_resolved_config(cli)?;
+    let (checkpoint, copied) = Checkpoint::create(&cfg)?;
+    println!(
+        "checkpoint {}: {} entries, {} files copied to {}",
+        checkpoint.id,
+        checkpoint.entries.len(),
+        copied,
+        cfg.checkpoint_dir().display()
+    );
+    Ok(())
+}
+
+fn watch_command(cli: &Cli, from: bool) -> Result<()> {
+    let cfg = load_config_for_writing(cli)?;
+    let direction = if from {
+        Direction::FromDisk
+    } else {
+        Direction::ToDisk
+    };
+    let stop = stop_on_signals()?;
+    let pressure = BackgroundPressure::spawn(cfg.clone());
+    let result = watch(&cfg, direction, stop);
+    if let Some(pressure) = pressure {
+        pressure.stop();
+    }
+    result
+}
+
+fn undo_command(cli: &Cli, id: Option<&str>, noninteractive: bool) -> Result<()> {
+    let cfg = load_config_for_writing(cli)?;
+    let backup = Backup::find(&cfg, id)?;
+    for entry in &backup.entries {
+        let marker = if entry.existed { 'R' } else { 'D' };
+        println!("{marker} {}", entry.path.display());
+    }
+    let prompt = format!(
+        "Restore {} files on disk from backup {}?",
+        backup.entries.len(),
+        backup.id
+    );
+    if ramws::syncer::confirm_if_needed(&prompt, noninteractive)? {
+        backup.restore(&cfg)?;
+        println!(
+            "restored backup {}; run `ramws sync --from` to refresh the workspace",
+            backup.id
+        );
+    }
+    Ok(())
+}
+
+fn backups_command(cli: &Cli, action: &BackupsAction) -> Result<()> {
+    let cfg = load_resolved_config(cli)?;
+    match action {
+        BackupsAction::List => {
+            let backups = Backup::list(&cfg)?;
+            if cli.json {
+                println!("{}", serde_json::to_string_pretty(&backups)?);
+            } else if backups.is_empty() {
+                println!("no backups");
+            } else {
+                for b in &backups {
+                    println!(
+                        "{}  {} files  {}",
+                        b.id,
+                        b.entries.len(),
+                        format_bytes(b.size(&cfg))
+                    );
+                }
+            }
+        }
+        BackupsAction::Prune { keep } => {
+            let removed = backup::prune(&cfg, *keep)?;
+            println!("removed {} backups", removed.len());
+        }
+    }
+    Ok(())
+}
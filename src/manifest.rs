@@ -0,0 +1,137 @@
+use crate::config::ResolvedConfig;
+use crate::native::list_files;
+use crate::syncer::source_options;
+use crate::util::{ensure_dir, hash_file, join_relative};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha1::Digest;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// State of one file as it was copied into the workspace.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ManifestEntry {
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub hash: String,
+}
+
+impl ManifestEntry {
+    /// Reads the current state of `path`; `None` when it does not exist.
+    pub fn from_path(path: &Path) -> Result<Option<Self>> {
+        let meta = match fs::symlink_metadata(path) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("failed to stat {}", path.display())),
+        };
+        if meta.is_dir() {
+            return Ok(None);
+        }
+        Ok(Some(ManifestEntry {
+            size: meta.len(),
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec(),
+            hash: hash_entry(path, &meta)?,
+        }))
+    }
+
+    /// Whether `path` still holds this content. Matching size and mtime is
+    /// taken as proof; otherwise the content hash decides.
+    pub fn matches(&self, path: &Path) -> Result<bool> {
+        let meta = match fs::symlink_metadata(path) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e).with_context(|| format!("failed to stat {}", path.display())),
+        };
+        if meta.is_dir() || meta.len() != self.size {
+            return Ok(false);
+        }
+        if (meta.mtime(), meta.mtime_nsec()) == (self.mtime, self.mtime_nsec) {
+            return Ok(true);
+        }
+        Ok(hash_entry(path, &meta)? == self.hash)
+    }
+}
+
+fn hash_entry(path: &Path, meta: &fs::Metadata) -> Result<String> {
+    if meta.file_type().is_symlink() {
+        let target = fs::read_link(path)
+            .with_context(|| format!("failed to read link {}", path.display()))?;
+        let digest = sha1::Sha1::digest(target.as_os_str().as_bytes());
+        return Ok(format!("{:x}", digest));
+    }
+    hash_file(path)
+}
+
+/// Baseline of what `Workspace::ensure` copied into RAM, keyed by
+/// project-relative path. Sync back compares both sides against it to tell
+/// RAM edits from disk edits.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Manifest {
+    pub entries: BTreeMap<PathBuf, ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn location(cfg: &ResolvedConfig) -> PathBuf {
+        cfg.state_dir().join("baseline.json")
+    }
+
+    pub fn load(cfg: &ResolvedConfig) -> Result<Option<Self>> {
+        let path = Self::location(cfg);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        let manifest = serde_json::from_str(&text)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        Ok(Some(manifest))
+    }
+
+    pub fn save(&self, cfg: &ResolvedConfig) -> Result<()> {
+        ensure_dir(&cfg.state_dir())?;
+        let path = Self::location(cfg);
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &path).with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Records every source file currently in the workspace.
+    pub fn record(cfg: &ResolvedConfig) -> Result<Self> {
+        let mut manifest = Manifest::default();
+        for source in &cfg.raw.sources {
+            let opts = source_options(cfg, source);
+            let root = cfg.workspace_root.join(&source.path);
+            for rel in list_files(&root, &opts)? {
+                if let Some(entry) = ManifestEntry::from_path(&root.join(&rel))? {
+                    manifest
+                        .entries
+                        .insert(join_relative(&source.path, &rel), entry);
+                }
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Re-reads the given project-relative paths from `root`, dropping entries
+    /// for files that no longer exist there.
+    pub fn update_from(&mut self, root: &Path, paths: &[PathBuf]) -> Result<()> {
+        for path in paths {
+            match ManifestEntry::from_path(&root.join(path))? {
+                Some(entry) => {
+                    self.entries.insert(path.clone(), entry);
+                }
+                None => {
+                    self.entries.remove(path);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use crate::config::{ResolvedConfig, WorkspaceMode};
+use crate::overlay;
+use crate::util::{ensure_dir, find_in_path, is_empty_dir};
+use anyhow::{Context, Result};
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
+use nix::sched::{unshare, CloneFlags};
+use nix::unistd::{getgid, getuid, Uid};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process::Command;
+use tracing::{info, warn};
+
+/// How the workspace root ended up backed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MountKind {
+    /// A tmpfs mounted for this workspace, visible to every process.
+    Private,
+    /// A tmpfs inside the mount namespace of the current `ramws shell`.
+    Namespace,
+    /// A plain directory on whatever filesystem holds the root.
+    Plain,
+}
+
+/// Whether `path` is the root of a mount, i.e. lives on another device than
+/// its parent.
+pub fn is_mount_point(path: &Path) -> bool {
+    let (Ok(meta), Some(parent)) = (fs::metadata(path), path.parent()) else {
+        return false;
+    };
+    fs::metadata(parent).is_ok_and(|p| p.dev() != meta.dev())
+}
+
+/// Mounts the private tmpfs from `workspace.mount` at the workspace root, or
+/// the overlay in `overlay` mode.
+///
+/// As root the tmpfs is mounted directly and outlives ramws. Otherwise, and
+/// only when `session` is set (`ramws shell`), ramws moves itself into a new
+/// user and mount namespace and mounts it there; the shell and everything it
+/// starts see the mount, and it is discarded when the shell exits. When
+/// neither works the workspace stays a plain directory.
+pub fn mount_workspace(cfg: &ResolvedConfig, session: bool) -> Result<MountKind> {
+    if cfg.raw.workspace.mode == WorkspaceMode::Overlay {
+        return overlay::mount_overlay(cfg, session);
+    }
+    let Some(spec) = &cfg.raw.workspace.mount else {
+        return Ok(MountKind::Plain);
+    };
+    let root = &cfg.workspace_root;
+    if is_mount_point(root) {
+        return Ok(MountKind::Private);
+    }
+    if !is_empty_dir(root)? {
+        warn!(
+            "{} already holds a workspace; not mounting a tmpfs over it (run ramws destroy first)",
+            root.display()
+        );
+        return Ok(MountKind::Plain);
+    }
+    ensure_dir(root)?;
+    let options = spec.options();
+    if Uid::effective().is_root() {
+        match mount_tmpfs(root, &options) {
+            Ok(()) => {
+                info!("mounted tmpfs ({options}) at {}", root.display());
+                return Ok(MountKind::Private);
+            }
+            Err(e) => warn!("failed to mount tmpfs at {}: {e:#}", root.display()),
+        }
+    } else if session {
+        match enter_namespace().and_then(|()| mount_tmpfs(root, &options)) {
+            Ok(()) => {
+                warn!(
+                    "mounted tmpfs ({options}) at {} in a private namespace; it is only visible \
+                     inside this shell and is discarded when the shell exits",
+                    root.display()
+                );
+                return Ok(MountKind::Namespace);
+            }
+            Err(e) => warn!("failed to set up a private mount namespace: {e:#}"),
+        }
+    } else {
+        warn!(
+            "workspace.mount needs root outside ramws shell; {} is a plain directory",
+            root.display()
+        );
+        return Ok(MountKind::Plain);
+    }
+    warn!("falling back to a plain directory at {}", root.display());
+    Ok(MountKind::Plain)
+}
+
+/// Detaches the tmpfs or overlay mounted at the workspace root.
+pub fn unmount_workspace(cfg: &ResolvedConfig) -> Result<()> {
+    let root = &cfg.workspace_root;
+    if !is_mount_point(root) {
+        return Ok(());
+    }
+    if let Err(e) = umount2(root, MntFlags::MNT_DETACH) {
+        // fuse-overlayfs mounts made without root are released by fusermount.
+        let fusermount = find_in_path("fusermount3").or_else(|| find_in_path("fusermount"));
+        let released = fusermount.is_some_and(|bin| {
+            Command::new(bin)
+                .arg("-u")
+                .arg(root)
+                .status()
+                .is_ok_and(|s| s.success())
+        });
+        if !released {
+            return Err(e).with_context(|| format!("failed to unmount {}", root.display()));
+        }
+    }
+    info!("unmounted {}", root.display());
+    Ok(())
+}
+
+fn mount_tmpfs(root: &Path, options: &str) -> Result<()> {
+    mount(
+        Some("tmpfs"),
+        root,
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        Some(options),
+    )
+    .with_context(|| format!("mount -t tmpfs -o {options} failed"))
+}
+
+/// Moves this process into a fresh user and mount namespace, mapping the
+/// current user to itself. Must run before any threads are started.
+pub(crate) fn enter_namespace() -> Result<()> {
+    let (uid, gid) = (getuid(), getgid());
+    unshare(CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNS)
+        .context("unshare(CLONE_NEWUSER | CLONE_NEWNS) failed")?;
+    match fs::write("/proc/self/setgroups", "deny") {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(e).context("failed to write /proc/self/setgroups")
+        }
+        _ => {}
+    }
+    fs::write("/proc/self/uid_map", format!("{uid} {uid} 1"))
+        .context("failed to write /proc/self/uid_map")?;
+    fs::write("/proc/self/gid_map", format!("{gid} {gid} 1"))
+        .context("failed to write /proc/self/gid_map")?;
+    // Keep our mounts from propagating back to the parent namespace.
+    mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        None::<&str>,
+    )
+    .context("failed to make / private")
+}
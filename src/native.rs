@@ -0,0 +1,629 @@
+use crate::git::Members;
+use crate::ignore_files::{IgnoreFiles, IgnoreMatcher};
+use crate::syncer::{ChangeKind, FileChange, FileType, SyncBackend, SyncOptions};
+use crate::util::hash_file;
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use nix::libc;
+use nix::sys::stat::{utimensat, UtimensatFlags};
+use nix::sys::time::TimeSpec;
+use nix::unistd::Uid;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{lchown, symlink, MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use tracing::debug;
+use walkdir::WalkDir;
+
+/// Top-level names owned by ramws itself; never mirrored and never deleted.
+const INTERNAL_NAMES: [&str; 2] = [".ramws", ".ramws-staging"];
+
+/// Suffix of the temporary files written next to their target before being
+/// renamed into place.
+pub(crate) const TMP_SUFFIX: &str = ".ramws-tmp";
+
+/// Include/exclude rules with rsync-like semantics: a pattern starting with
+/// `/` is anchored at the sync root, anything else may match at any depth,
+/// and includes win over excludes. A source's `select` narrows the tree
+/// before any pattern applies.
+pub struct Filter {
+    include: GlobSet,
+    exclude: GlobSet,
+    exclude_dirs: GlobSet,
+    ignored: Option<IgnoreMatcher>,
+    members: Option<Members>,
+}
+
+impl Filter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        let mut inc = GlobSetBuilder::new();
+        for pattern in include {
+            inc.add(compile_glob(pattern)?);
+        }
+        let mut exc = GlobSetBuilder::new();
+        let mut exc_dirs = GlobSetBuilder::new();
+        for pattern in exclude {
+            exc.add(compile_glob(pattern)?);
+            // `dir/**` should prune the directory itself, not only its contents.
+            if let Some(dir) = pattern.trim_end_matches('/').strip_suffix("/**") {
+                if !dir.is_empty() {
+                    exc_dirs.add(compile_glob(dir)?);
+                }
+            }
+        }
+        Ok(Filter {
+            include: inc.build()?,
+            exclude: exc.build()?,
+            exclude_dirs: exc_dirs.build()?,
+            ignored: None,
+            members: None,
+        })
+    }
+
+    /// The filter for a sync with `opts`, including its ignore files and
+    /// `select` members.
+    pub fn for_options(opts: &SyncOptions) -> Result<Self> {
+        let mut filter = Filter::new(&opts.include, &opts.exclude)?;
+        filter.ignored = opts.ignore_files.as_ref().map(IgnoreFiles::matcher);
+        if let Some(select) = &opts.select {
+            filter.members = Some(select.members(&filter)?);
+        }
+        Ok(filter)
+    }
+
+    pub fn is_excluded(&self, rel: &Path, is_dir: bool) -> bool {
+        if let Some(Component::Normal(first)) = rel.components().next() {
+            if INTERNAL_NAMES.iter().any(|n| first == *n) {
+                return true;
+            }
+        }
+        if rel
+            .file_name()
+            .is_some_and(|name| name.as_bytes().ends_with(TMP_SUFFIX.as_bytes()))
+        {
+            return true;
+        }
+        if self
+            .members
+            .as_ref()
+            .is_some_and(|m| !m.contains(rel, is_dir))
+        {
+            return true;
+        }
+        if rel.as_os_str().is_empty() || self.include.is_match(rel) {
+            return false;
+        }
+        self.exclude.is_match(rel)
+            || (is_dir && self.exclude_dirs.is_match(rel))
+            || self
+                .ignored
+                .as_ref()
+                .is_some_and(|m| m.is_ignored(rel, is_dir))
+    }
+
+    /// Like `is_excluded`, but also checks every parent of `rel`, for paths
+    /// that do not come from a pruned walk.
+    pub fn excludes_path(&self, rel: &Path, is_dir: bool) -> bool {
+        if rel.as_os_str().is_empty() {
+            return self.is_excluded(rel, is_dir);
+        }
+        let mut prefix = PathBuf::new();
+        rel.components().any(|part| {
+            prefix.push(part);
+            self.is_excluded(&prefix, prefix != rel || is_dir)
+        })
+    }
+}
+
+/// Rewrites patterns written relative to a source root so they apply to the
+/// subtree `sub` of that root. Anchored patterns that cannot match inside
+/// `sub` are dropped; floating patterns are kept and, when they spell out a
+/// path through `sub`, also added in anchored form.
+pub fn rebase_patterns(patterns: &[String], sub: &Path) -> Vec<String> {
+    let sub: Vec<String> = sub
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect();
+    if sub.is_empty() {
+        return patterns.to_vec();
+    }
+    let mut rebased = Vec::new();
+    for pattern in patterns {
+        match pattern.strip_prefix('/') {
+            Some(anchored) => rebased.extend(strip_leading(anchored, &sub)),
+            None => {
+                rebased.push(pattern.clone());
+                for start in 0..sub.len() {
+                    rebased.extend(strip_leading(pattern, &sub[start..]));
+                }
+            }
+        }
+    }
+    rebased.sort();
+    rebased.dedup();
+    rebased
+}
+
+/// Consumes the components of `prefix` from the front of `pattern`, returning
+/// the remainder as an anchored pattern.
+fn strip_leading(pattern: &str, prefix: &[String]) -> Option<String> {
+    let parts: Vec<&str> = pattern.trim_end_matches('/').split('/').collect();
+    for (i, dir) in prefix.iter().enumerate() {
+        let part = *parts.get(i)?;
+        if part == "**" {
+            return Some(format!("/{}", parts[i..].join("/")));
+        }
+        let matches = part == dir
+            || GlobBuilder::new(part)
+                .literal_separator(true)
+                .build()
+                .is_ok_and(|g| g.compile_matcher().is_match(dir));
+        if !matches {
+            return None;
+        }
+    }
+    let rest = &parts[prefix.len().min(parts.len())..];
+    if rest.is_empty() {
+        None
+    } else {
+        Some(format!("/{}", rest.join("/")))
+    }
+}
+
+/// Compiles patterns with the same anchoring rules as filters, for callers
+/// that only need to know whether a path matches.
+pub fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut set = GlobSetBuilder::new();
+    for pattern in patterns {
+        set.add(compile_glob(pattern)?);
+    }
+    Ok(set.build()?)
+}
+
+fn compile_glob(pattern: &str) -> Result<globset::Glob> {
+    let trimmed = pattern.trim_end_matches('/');
+    let expr = match trimmed.strip_prefix('/') {
+        Some(anchored) => anchored.to_string(),
+        None => format!("**/{trimmed}"),
+    };
+    GlobBuilder::new(&expr)
+        .literal_separator(true)
+        .build()
+        .with_context(|| format!("invalid glob pattern {pattern}"))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum EntryKind {
+    File,
+    Dir,
+    Symlink(PathBuf),
+    /// FIFOs, sockets and device nodes, which are skipped like `rsync -a`
+    /// does without `--specials` and `--devices`.
+    Special,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    kind: EntryKind,
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+}
+
+impl Entry {
+    fn from_metadata(path: &Path, meta: &fs::Metadata) -> Result<Self> {
+        let ft = meta.file_type();
+        let kind = if ft.is_dir() {
+            EntryKind::Dir
+        } else if ft.is_symlink() {
+            EntryKind::Symlink(
+                fs::read_link(path)
+                    .with_context(|| format!("failed to read link {}", path.display()))?,
+            )
+        } else if ft.is_file() {
+            EntryKind::File
+        } else {
+            EntryKind::Special
+        };
+        Ok(Entry {
+            kind,
+            size: meta.len(),
+            mtime: meta.mtime(),
+            mtime_nsec: meta.mtime_nsec(),
+            mode: meta.mode() & 0o7777,
+            uid: meta.uid(),
+            gid: meta.gid(),
+        })
+    }
+
+    fn load(path: &Path) -> Result<Option<Self>> {
+        match fs::symlink_metadata(path) {
+            Ok(meta) => Ok(Some(Entry::from_metadata(path, &meta)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("failed to stat {}", path.display())),
+        }
+    }
+
+    fn file_type(&self) -> FileType {
+        match self.kind {
+            EntryKind::File | EntryKind::Special => FileType::File,
+            EntryKind::Dir => FileType::Dir,
+            EntryKind::Symlink(_) => FileType::Symlink,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        self.kind == EntryKind::Dir
+    }
+
+    fn is_special(&self) -> bool {
+        self.kind == EntryKind::Special
+    }
+}
+
+type Tree = BTreeMap<PathBuf, Entry>;
+
+/// Resolves a tree-relative path, treating the empty path as the root itself so
+/// that single files can be synced as well as directories.
+pub(crate) fn target(root: &Path, rel: &Path) -> PathBuf {
+    if rel.as_os_str().is_empty() {
+        root.to_path_buf()
+    } else {
+        root.join(rel)
+    }
+}
+
+fn scan(root: &Path, filter: &Filter) -> Result<Tree> {
+    let mut tree = Tree::new();
+    match Entry::load(root)? {
+        None => return Ok(tree),
+        Some(entry) if !entry.is_dir() => {
+            if !entry.is_special() && !filter.is_excluded(Path::new(""), false) {
+                tree.insert(PathBuf::new(), entry);
+            }
+            return Ok(tree);
+        }
+        Some(_) => {}
+    }
+    let walker = WalkDir::new(root)
+        .min_depth(1)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|e| {
+            let rel = e.path().strip_prefix(root).unwrap_or(e.path());
+            !filter.is_excluded(rel, e.file_type().is_dir())
+        });
+    for item in walker {
+        let item = item.with_context(|| format!("failed to walk {}", root.display()))?;
+        let rel = item.path().strip_prefix(root)?.to_path_buf();
+        let meta = item
+            .metadata()
+            .with_context(|| format!("failed to stat {}", item.path().display()))?;
+        let entry = Entry::from_metadata(item.path(), &meta)?;
+        if entry.is_special() {
+            debug!("skipping non-regular file {}", item.path().display());
+            continue;
+        }
+        tree.insert(rel, entry);
+    }
+    Ok(tree)
+}
+
+/// Non-directory entries under `root` that pass the filters in `opts`, as
+/// paths relative to `root`.
+pub fn list_files(root: &Path, opts: &SyncOptions) -> Result<Vec<PathBuf>> {
+    let filter = Filter::for_options(opts)?;
+    Ok(scan(root, &filter)?
+        .into_iter()
+        .filter(|(_, entry)| !entry.is_dir())
+        .map(|(rel, _)| rel)
+        .collect())
+}
+
+/// Total size of the non-directory entries under `root` that pass the
+/// filters in `opts`.
+pub fn tree_size(root: &Path, opts: &SyncOptions) -> Result<u64> {
+    let filter = Filter::for_options(opts)?;
+    Ok(scan(root, &filter)?
+        .values()
+        .filter(|entry| !entry.is_dir())
+        .map(|entry| entry.size)
+        .sum())
+}
+
+fn content_differs(
+    source: &Path,
+    dest: &Path,
+    rel: &Path,
+    s: &Entry,
+    d: &Entry,
+    checksum: bool,
+) -> Result<bool> {
+    if s.size != d.size {
+        return Ok(true);
+    }
+    if checksum {
+        return Ok(hash_file(&target(source, rel))? != hash_file(&target(dest, rel))?);
+    }
+    // Like rsync's quick check, at full precision: an edit in the same second
+    // as the copy must not go unnoticed.
+    Ok((s.mtime, s.mtime_nsec) != (d.mtime, d.mtime_nsec))
+}
+
+fn change(rel: &Path, kind: ChangeKind, entry: &Entry, size_delta: i64) -> FileChange {
+    FileChange {
+        path: rel.to_path_buf(),
+        kind,
+        file_type: entry.file_type(),
+        size_delta,
+    }
+}
+
+/// Built-in backend that walks both trees and mirrors them like `rsync -a`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NativeBackend;
+
+impl SyncBackend for NativeBackend {
+    fn name(&self) -> &'static str {
+        "native"
+    }
+
+    fn plan(&self, source: &Path, dest: &Path, opts: &SyncOptions) -> Result<Vec<FileChange>> {
+        let filter = Filter::for_options(opts)?;
+        let src = scan(source, &filter)?;
+        let dst = scan(dest, &filter)?;
+        let mut changes = Vec::new();
+        for (rel, s) in &src {
+            let Some(d) = dst.get(rel) else {
+                changes.push(change(rel, ChangeKind::Added, s, s.size as i64));
+                continue;
+            };
+            let modified = match (&s.kind, &d.kind) {
+                (EntryKind::File, EntryKind::File) => {
+                    content_differs(source, dest, rel, s, d, opts.checksum)? || s.mode != d.mode
+                }
+                (EntryKind::Dir, EntryKind::Dir) => s.mode != d.mode,
+                (EntryKind::Symlink(a), EntryKind::Symlink(b)) => a != b,
+                _ => true,
+            };
+            if modified {
+                let delta = s.size as i64 - d.size as i64;
+                changes.push(change(rel, ChangeKind::Modified, s, delta));
+            }
+        }
+        if opts.delete {
+            // Reverse order visits children before their parent directories.
+            for (rel, d) in dst.iter().rev() {
+                if !src.contains_key(rel) {
+                    changes.push(change(rel, ChangeKind::Deleted, d, -(d.size as i64)));
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    fn apply(
+        &self,
+        source: &Path,
+        dest: &Path,
+        changes: &[FileChange],
+        _opts: &SyncOptions,
+    ) -> Result<()> {
+        if fs::symlink_metadata(source).is_ok_and(|m| m.is_dir()) {
+            fs::create_dir_all(dest)
+                .with_context(|| format!("failed to create {}", dest.display()))?;
+        } else if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let (mut deletes, mut writes): (Vec<&FileChange>, Vec<&FileChange>) =
+            changes.iter().partition(|c| c.kind == ChangeKind::Deleted);
+        deletes.sort_by(|a, b| b.path.cmp(&a.path));
+        writes.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut touched = BTreeSet::new();
+        let mut copied_dirs = BTreeSet::new();
+        for c in deletes {
+            remove_entry(&target(dest, &c.path))?;
+            touched.extend(c.path.parent().map(Path::to_path_buf));
+        }
+        for c in writes {
+            let src_path = target(source, &c.path);
+            let Some(entry) = Entry::load(&src_path)? else {
+                debug!("{} vanished before it could be copied", src_path.display());
+                continue;
+            };
+            if entry.is_special() {
+                debug!("skipping non-regular file {}", src_path.display());
+                continue;
+            }
+            let dst = target(dest, &c.path);
+            if let Some(existing) = Entry::load(&dst)? {
+                if existing.file_type() != entry.file_type() {
+                    remove_replaced(&dst, &existing)?;
+                }
+            }
+            copy_entry(&src_path, &dst, &entry)?;
+            touched.extend(c.path.parent().map(Path::to_path_buf));
+            if entry.is_dir() {
+                touched.insert(c.path.clone());
+                copied_dirs.insert(c.path.clone());
+            }
+        }
+        // Directory mtimes move whenever children change, so restore them last,
+        // innermost first, and flush the renames that landed in them. Modes
+        // wait until then too: a read-only directory would refuse its children.
+        for rel in touched.iter().rev() {
+            if let Some(entry) = Entry::load(&target(source, rel))? {
+                if entry.is_dir() {
+                    let dir = target(dest, rel);
+                    set_mtime(&dir, &entry)?;
+                    if copied_dirs.contains(rel) {
+                        set_mode(&dir, &entry)?;
+                    }
+                }
+            }
+            sync_dir(&target(dest, rel));
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn remove_entry(path: &Path) -> Result<()> {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("failed to stat {}", path.display())),
+    };
+    if meta.is_dir() {
+        // Excluded entries may still live below this directory; they are
+        // protected, so a non-empty directory is left in place.
+        if let Err(e) = fs::remove_dir(path) {
+            debug!("keeping directory {}: {e}", path.display());
+        }
+        Ok(())
+    } else {
+        fs::remove_file(path).with_context(|| format!("failed to delete {}", path.display()))
+    }
+}
+
+/// Clears the way for an entry of another type. Unlike a planned deletion, a
+/// directory that still holds excluded or undeleted entries cannot be kept.
+fn remove_replaced(path: &Path, existing: &Entry) -> Result<()> {
+    if !existing.is_dir() {
+        return remove_entry(path);
+    }
+    fs::remove_dir(path).with_context(|| {
+        format!(
+            "cannot replace directory {} with a file: it still holds entries that are not synced",
+            path.display()
+        )
+    })
+}
+
+/// Copies a single file or symlink with its metadata; returns false when `src`
+/// does not exist or is a special file.
+pub(crate) fn copy_path(src: &Path, dst: &Path) -> Result<bool> {
+    match Entry::load(src)? {
+        Some(entry) if !entry.is_special() => copy_entry(src, dst, &entry).map(|_| true),
+        _ => Ok(false),
+    }
+}
+
+/// Gives the directory `dst` the mode and mtime of `src`, once its children
+/// are in place.
+pub(crate) fn copy_dir_metadata(src: &Path, dst: &Path) -> Result<()> {
+    match Entry::load(src)? {
+        Some(entry) if entry.is_dir() => {
+            set_mode(dst, &entry)?;
+            set_mtime(dst, &entry)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn copy_entry(src: &Path, dst: &Path, entry: &Entry) -> Result<()> {
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    match &entry.kind {
+        EntryKind::Dir => {
+            if !dst.is_dir() {
+                fs::create_dir(dst)
+                    .with_context(|| format!("failed to create {}", dst.display()))?;
+            }
+            // The mode and mtime are set once the children are in place.
+            chown_entry(dst, entry);
+            Ok(())
+        }
+        EntryKind::Symlink(link) => {
+            let tmp = tmp_path(dst);
+            remove_tmp(&tmp)?;
+            symlink(link, &tmp).with_context(|| format!("failed to link {}", dst.display()))?;
+            chown_entry(&tmp, entry);
+            set_mtime(&tmp, entry)?;
+            install(&tmp, dst)
+        }
+        EntryKind::Special => Ok(()),
+        EntryKind::File => {
+            // Readers of `dst` see either the old or the new content, never a
+            // partial copy, and a crash leaves at most a stray temp file.
+            let tmp = tmp_path(dst);
+            remove_tmp(&tmp)?;
+            fs::copy(src, &tmp).with_context(|| {
+                format!("failed to copy {} to {}", src.display(), tmp.display())
+            })?;
+            // Flushed before the mode is applied, which may take away read
+            // access.
+            File::open(&tmp)
+                .and_then(|f| f.sync_all())
+                .with_context(|| format!("failed to fsync {}", tmp.display()))?;
+            apply_metadata(&tmp, entry)?;
+            install(&tmp, dst)
+        }
+    }
+}
+
+/// Temp file used while replacing `path`: `.<name>.ramws-tmp` beside it.
+pub(crate) fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(TMP_SUFFIX);
+    path.with_file_name(name)
+}
+
+/// Removes a temp file left behind by an interrupted copy.
+pub(crate) fn remove_tmp(tmp: &Path) -> Result<()> {
+    match fs::remove_file(tmp) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("failed to remove {}", tmp.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn install(tmp: &Path, dst: &Path) -> Result<()> {
+    fs::rename(tmp, dst).with_context(|| format!("failed to rename into {}", dst.display()))
+}
+
+fn sync_dir(path: &Path) {
+    if let Err(e) = File::open(path).and_then(|d| d.sync_all()) {
+        debug!("failed to fsync {}: {e}", path.display());
+    }
+}
+
+fn apply_metadata(path: &Path, entry: &Entry) -> Result<()> {
+    chown_entry(path, entry);
+    set_mode(path, entry)?;
+    set_mtime(path, entry)
+}
+
+fn set_mode(path: &Path, entry: &Entry) -> Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(entry.mode))
+        .with_context(|| format!("failed to chmod {}", path.display()))
+}
+
+/// Ownership is only preserved when running as root, matching `rsync -a`.
+fn chown_entry(path: &Path, entry: &Entry) {
+    if Uid::effective().is_root() {
+        if let Err(e) = lchown(path, Some(entry.uid), Some(entry.gid)) {
+            debug!("failed to chown {}: {e}", path.display());
+        }
+    }
+}
+
+/// Sets the mtime by path and without following symlinks, so it works on
+/// unreadable files and directories and on the links themselves.
+fn set_mtime(path: &Path, entry: &Entry) -> Result<()> {
+    let atime = TimeSpec::new(0, libc::UTIME_OMIT);
+    let mtime = TimeSpec::new(entry.mtime, entry.mtime_nsec);
+    utimensat(None, path, &atime, &mtime, UtimensatFlags::NoFollowSymlink)
+        .with_context(|| format!("failed to set mtime on {}", path.display()))
+}
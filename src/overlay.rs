@@ -0,0 +1,619 @@
+use crate::config::{ResolvedConfig, WorkspaceMode};
+use crate::journal::{self, Journal};
+use crate::manifest::Manifest;
+use crate::mount::{enter_namespace, is_mount_point, MountKind};
+use crate::native::{self, NativeBackend};
+use crate::syncer::{
+    apply_back, options_for_path, ChangeKind, FileChange, FileType, PathPlan, SyncBackOptions,
+};
+use crate::util::{ensure_dir, find_in_path, is_empty_dir, join_relative};
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
+use nix::sys::signal::kill;
+use nix::sys::statfs::{statfs, FUSE_SUPER_MAGIC};
+use nix::unistd::{Pid, Uid};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{debug, info, warn};
+use walkdir::WalkDir;
+
+/// Names overlayfs (or fuse-overlayfs) uses to mark a directory as opaque,
+/// i.e. replacing rather than merging with the lower one.
+const OPAQUE_XATTRS: [&str; 3] = [
+    "trusted.overlay.opaque",
+    "user.overlay.opaque",
+    "user.fuseoverlayfs.opaque",
+];
+/// fuse-overlayfs falls back to marker files when it cannot create device
+/// nodes or set xattrs.
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_MARKER: &str = ".wh..wh..opq";
+
+pub fn upper_dir(cfg: &ResolvedConfig) -> PathBuf {
+    cfg.overlay_dir().join("upper")
+}
+
+fn work_dir(cfg: &ResolvedConfig) -> PathBuf {
+    cfg.overlay_dir().join("work")
+}
+
+fn layer_options(cfg: &ResolvedConfig) -> String {
+    format!(
+        "lowerdir={},upperdir={},workdir={}",
+        cfg.orig_root.display(),
+        upper_dir(cfg).display(),
+        work_dir(cfg).display()
+    )
+}
+
+/// Mounts the overlay at the workspace root: as root with the kernel
+/// overlayfs, otherwise inside a user namespace for `ramws shell` sessions or
+/// through fuse-overlayfs when it is installed.
+pub(crate) fn mount_overlay(cfg: &ResolvedConfig, session: bool) -> Result<MountKind> {
+    let root = &cfg.workspace_root;
+    if is_mount_point(root) {
+        return Ok(MountKind::Private);
+    }
+    if !is_empty_dir(root)? {
+        bail!(
+            "{} already holds a copied workspace; run ramws destroy before switching to overlay mode",
+            root.display()
+        );
+    }
+    if cfg.raw.workspace.mount.is_some() {
+        warn!("workspace.mount is ignored in overlay mode");
+    }
+    ensure_dir(root)?;
+    ensure_dir(&upper_dir(cfg))?;
+    ensure_dir(&work_dir(cfg))?;
+    let options = layer_options(cfg);
+    if Uid::effective().is_root() {
+        mount_kernel(root, &options)?;
+        info!("mounted overlay at {}", root.display());
+        return Ok(MountKind::Private);
+    }
+    if session {
+        let namespaced =
+            enter_namespace().and_then(|()| mount_kernel(root, &format!("{options},userxattr")));
+        match namespaced {
+            Ok(()) => {
+                warn!(
+                    "mounted overlay at {} in a private namespace; it is only visible inside \
+                     this shell and is unmounted when the shell exits",
+                    root.display()
+                );
+                record_session(cfg)?;
+                return Ok(MountKind::Namespace);
+            }
+            Err(e) => warn!("failed to mount overlay in a user namespace: {e:#}"),
+        }
+    }
+    if find_in_path("fuse-overlayfs").is_some() {
+        mount_fuse(root, &options)?;
+        return Ok(MountKind::Private);
+    }
+    bail!(
+        "overlay mode needs root, fuse-overlayfs, or an unprivileged user namespace in ramws shell"
+    )
+}
+
+fn mount_kernel(root: &Path, options: &str) -> Result<()> {
+    mount(
+        Some("overlay"),
+        root,
+        Some("overlay"),
+        MsFlags::empty(),
+        Some(options),
+    )
+    .with_context(|| format!("mount -t overlay -o {options} failed"))
+}
+
+fn mount_fuse(root: &Path, options: &str) -> Result<()> {
+    let fuse = find_in_path("fuse-overlayfs").context("fuse-overlayfs is not in PATH")?;
+    let status = Command::new(fuse)
+        .arg("-o")
+        .arg(options)
+        .arg(root)
+        .status()
+        .context("failed to run fuse-overlayfs")?;
+    if !status.success() {
+        bail!("fuse-overlayfs exited with {status}");
+    }
+    info!("mounted fuse-overlayfs at {}", root.display());
+    Ok(())
+}
+
+/// Where a namespaced mount records the pid of the `ramws shell` holding it.
+/// Other processes cannot see that mount, but must not write to the lower
+/// layer while it exists.
+fn session_file(cfg: &ResolvedConfig) -> PathBuf {
+    cfg.state_dir().join("overlay-session")
+}
+
+fn record_session(cfg: &ResolvedConfig) -> Result<()> {
+    ensure_dir(&cfg.state_dir())?;
+    let path = session_file(cfg);
+    fs::write(&path, std::process::id().to_string())
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// The pid of another, still running `ramws shell` that has the overlay
+/// mounted in its namespace.
+fn session_pid(cfg: &ResolvedConfig) -> Option<i32> {
+    let pid: i32 = fs::read_to_string(session_file(cfg))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    (pid != std::process::id() as i32 && kill(Pid::from_raw(pid), None).is_ok()).then_some(pid)
+}
+
+/// Whether the workspace is an overlay whose mount is gone (a namespaced
+/// shell exited, or it was unmounted by hand) while its upper layer stays.
+/// Its root is then an empty dir that says nothing about the edits.
+pub fn is_unmounted(cfg: &ResolvedConfig) -> bool {
+    cfg.raw.workspace.mode == WorkspaceMode::Overlay
+        && cfg.workspace_root.exists()
+        && !is_mount_point(&cfg.workspace_root)
+}
+
+/// Fails while another `ramws shell` still has the overlay mounted.
+pub fn ensure_no_session(cfg: &ResolvedConfig) -> Result<()> {
+    if is_mount_point(&cfg.workspace_root) {
+        return Ok(());
+    }
+    if let Some(pid) = session_pid(cfg) {
+        bail!(
+            "the overlay at {} is mounted inside ramws shell (pid {pid}); run this from that \
+             shell or after it exits",
+            cfg.workspace_root.display()
+        );
+    }
+    Ok(())
+}
+
+/// How the overlay was mounted, to mount it again the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layers {
+    Kernel,
+    Fuse,
+}
+
+/// Unmounts the overlay so its layers can be changed. Unlike
+/// `unmount_workspace` this does not detach lazily: when anything still uses
+/// the workspace it fails and the overlay stays mounted.
+fn unmount(cfg: &ResolvedConfig) -> Result<Layers> {
+    let root = &cfg.workspace_root;
+    let in_use = || {
+        format!(
+            "{} is in use; sync back in overlay mode has to unmount it, so leave the workspace \
+             (ramws shell syncs on exit) and retry",
+            root.display()
+        )
+    };
+    if statfs(root).is_ok_and(|s| s.filesystem_type() == FUSE_SUPER_MAGIC) {
+        let fusermount = find_in_path("fusermount3")
+            .or_else(|| find_in_path("fusermount"))
+            .context("fusermount is needed to unmount fuse-overlayfs")?;
+        let status = Command::new(fusermount)
+            .arg("-u")
+            .arg(root)
+            .status()
+            .context("failed to run fusermount")?;
+        if !status.success() {
+            bail!(in_use());
+        }
+        return Ok(Layers::Fuse);
+    }
+    match umount2(root, MntFlags::empty()) {
+        Ok(()) => Ok(Layers::Kernel),
+        Err(Errno::EBUSY) => bail!(in_use()),
+        Err(e) => Err(e).with_context(|| format!("failed to unmount {}", root.display())),
+    }
+}
+
+fn remount(cfg: &ResolvedConfig, layers: Layers) -> Result<()> {
+    let root = &cfg.workspace_root;
+    let options = layer_options(cfg);
+    match layers {
+        Layers::Fuse => mount_fuse(root, &options),
+        Layers::Kernel if Uid::effective().is_root() => mount_kernel(root, &options),
+        // Only reachable inside the namespace of `ramws shell`.
+        Layers::Kernel => mount_kernel(root, &format!("{options},userxattr")),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpperKind {
+    /// A file or symlink written in the overlay.
+    Write,
+    /// A directory created or copied up.
+    Dir,
+    /// A whiteout: the path was deleted in the overlay.
+    Delete,
+    /// A directory that replaced the lower one; anything below it that is not
+    /// in the upper layer was deleted.
+    Opaque,
+}
+
+/// One upper-layer entry, as a project-relative path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpperChange {
+    pub path: PathBuf,
+    pub kind: UpperKind,
+    /// The file in the upper layer that records the change, relative to it.
+    /// Differs from `path` for whiteout and opaque marker files.
+    pub entry: PathBuf,
+    pub size: u64,
+}
+
+fn is_opaque(path: &Path) -> bool {
+    path.join(OPAQUE_MARKER).exists()
+        || OPAQUE_XATTRS
+            .iter()
+            .any(|name| xattr::get(path, name).ok().flatten().as_deref() == Some(b"y"))
+}
+
+/// Whether sync back may touch the project-relative `rel`: it belongs to a
+/// source, passes its filters, and is not a build dir.
+fn in_scope(cfg: &ResolvedConfig, rel: &Path) -> Result<bool> {
+    let root = |p: &Path| join_relative(Path::new(""), p);
+    if cfg
+        .raw
+        .build_dirs
+        .iter()
+        .any(|b| rel.starts_with(root(&b.path)))
+    {
+        return Ok(false);
+    }
+    if !cfg
+        .raw
+        .sources
+        .iter()
+        .any(|s| rel.starts_with(root(&s.path)))
+    {
+        return Ok(false);
+    }
+    Ok(options_for_path(cfg, rel)?.is_some())
+}
+
+/// Everything in the upper layer that sync back would carry over, in walk
+/// order (parents before children).
+pub fn upper_changes(cfg: &ResolvedConfig) -> Result<Vec<UpperChange>> {
+    let upper = upper_dir(cfg);
+    let mut changes = Vec::new();
+    let mut walker = WalkDir::new(&upper).min_depth(1).into_iter();
+    while let Some(item) = walker.next() {
+        let item = item.with_context(|| format!("failed to walk {}", upper.display()))?;
+        let rel = item.path().strip_prefix(&upper)?.to_path_buf();
+        let meta = item
+            .path()
+            .symlink_metadata()
+            .with_context(|| format!("failed to stat {}", item.path().display()))?;
+        let parent = rel.parent().unwrap_or(Path::new("")).to_path_buf();
+        let name = rel.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        let (path, kind) = if meta.file_type().is_char_device() && meta.rdev() == 0 {
+            (rel, UpperKind::Delete)
+        } else if name == OPAQUE_MARKER {
+            (parent, UpperKind::Opaque)
+        } else if let Some(hidden) = name.strip_prefix(WHITEOUT_PREFIX) {
+            (parent.join(hidden), UpperKind::Delete)
+        } else if meta.is_dir() && is_opaque(item.path()) {
+            (rel, UpperKind::Opaque)
+        } else if meta.is_dir() {
+            (rel, UpperKind::Dir)
+        } else {
+            (rel, UpperKind::Write)
+        };
+        if !in_scope(cfg, &path)? {
+            if meta.is_dir() {
+                walker.skip_current_dir();
+            }
+            continue;
+        }
+        changes.push(UpperChange {
+            entry: item.path().strip_prefix(&upper)?.to_path_buf(),
+            path,
+            kind,
+            size: meta.len(),
+        });
+    }
+    Ok(changes)
+}
+
+/// Entries on disk below the project-relative `rel` that sync back may
+/// delete: in-scope files, and the directories holding them, deepest first.
+fn disk_entries_below(cfg: &ResolvedConfig, rel: &Path) -> Result<Vec<(PathBuf, FileType)>> {
+    let disk = cfg.orig_root.join(rel);
+    let meta = match fs::symlink_metadata(&disk) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).with_context(|| format!("failed to stat {}", disk.display())),
+    };
+    if !meta.is_dir() {
+        let file_type = if meta.file_type().is_symlink() {
+            FileType::Symlink
+        } else {
+            FileType::File
+        };
+        return Ok(vec![(rel.to_path_buf(), file_type)]);
+    }
+    let Some(opts) = options_for_path(cfg, rel)? else {
+        return Ok(vec![]);
+    };
+    let mut entries = Vec::new();
+    for file in native::list_files(&disk, &opts)? {
+        let file_type = if disk.join(&file).is_symlink() {
+            FileType::Symlink
+        } else {
+            FileType::File
+        };
+        entries.push((rel.join(file), file_type));
+    }
+    let mut dirs = BTreeSet::new();
+    for (file, _) in &entries {
+        dirs.extend(
+            file.ancestors()
+                .skip(1)
+                .take_while(|d| d.starts_with(rel))
+                .map(Path::to_path_buf),
+        );
+    }
+    // Directories that still hold protected or excluded entries are kept when
+    // the deletions are applied.
+    entries.extend(dirs.into_iter().rev().map(|d| (d, FileType::Dir)));
+    Ok(entries)
+}
+
+/// Upper-layer entries below any of the project-relative `selected` paths.
+fn selected_changes(cfg: &ResolvedConfig, selected: &[PathBuf]) -> Result<Vec<UpperChange>> {
+    Ok(upper_changes(cfg)?
+        .into_iter()
+        .filter(|c| selected.iter().any(|p| c.path.starts_with(p)))
+        .collect())
+}
+
+/// What applying `upper` would change on disk, as project-relative changes:
+/// written files are added or modified, whiteouts and files hidden by opaque
+/// dirs are deleted. Reads only the upper layer and the disk, so it works
+/// whether or not the overlay is mounted.
+fn disk_changes(cfg: &ResolvedConfig, upper: &[UpperChange]) -> Result<Vec<FileChange>> {
+    let upper_root = upper_dir(cfg);
+    let mut changes = Vec::new();
+    for change in upper {
+        let disk = fs::symlink_metadata(cfg.orig_root.join(&change.path)).ok();
+        match change.kind {
+            UpperKind::Write => {
+                let is_link = upper_root.join(&change.entry).is_symlink();
+                changes.push(FileChange {
+                    path: change.path.clone(),
+                    kind: if disk.is_some() {
+                        ChangeKind::Modified
+                    } else {
+                        ChangeKind::Added
+                    },
+                    file_type: if is_link {
+                        FileType::Symlink
+                    } else {
+                        FileType::File
+                    },
+                    size_delta: change.size as i64 - disk.map_or(0, |m| m.len() as i64),
+                });
+            }
+            UpperKind::Dir | UpperKind::Opaque => {
+                if !disk.is_some_and(|m| m.is_dir()) {
+                    changes.push(FileChange {
+                        path: change.path.clone(),
+                        kind: ChangeKind::Added,
+                        file_type: FileType::Dir,
+                        size_delta: 0,
+                    });
+                }
+                if change.kind == UpperKind::Dir {
+                    continue;
+                }
+                // Inside an opaque dir the overlay shows only the upper layer,
+                // so anything missing there was deleted.
+                for (path, file_type) in disk_entries_below(cfg, &change.path)? {
+                    if path != change.path && fs::symlink_metadata(upper_root.join(&path)).is_err()
+                    {
+                        changes.push(deletion(cfg, path, file_type));
+                    }
+                }
+            }
+            UpperKind::Delete => {
+                for (path, file_type) in disk_entries_below(cfg, &change.path)? {
+                    changes.push(deletion(cfg, path, file_type));
+                }
+            }
+        }
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes.dedup_by(|a, b| a.path == b.path);
+    Ok(changes)
+}
+
+fn deletion(cfg: &ResolvedConfig, path: PathBuf, file_type: FileType) -> FileChange {
+    let size = match file_type {
+        FileType::Dir => 0,
+        _ => fs::symlink_metadata(cfg.orig_root.join(&path)).map_or(0, |m| m.len() as i64),
+    };
+    FileChange {
+        path,
+        kind: ChangeKind::Deleted,
+        file_type,
+        size_delta: -size,
+    }
+}
+
+/// Unsynced changes in the upper layer, as project-relative changes.
+pub fn pending_changes(cfg: &ResolvedConfig) -> Result<Vec<FileChange>> {
+    disk_changes(cfg, &upper_changes(cfg)?)
+}
+
+/// Copies what `changes` writes from the upper layer into `staging`, with
+/// the modes and mtimes of the upper dirs.
+fn stage(cfg: &ResolvedConfig, staging: &Path, changes: &[FileChange]) -> Result<()> {
+    let upper = upper_dir(cfg);
+    let mut dirs = BTreeSet::from([PathBuf::new()]);
+    for change in changes.iter().filter(|c| c.kind != ChangeKind::Deleted) {
+        let dest = staging.join(&change.path);
+        if change.file_type == FileType::Dir {
+            ensure_dir(&dest)?;
+        } else {
+            native::copy_path(&upper.join(&change.path), &dest)?;
+        }
+        dirs.extend(change.path.ancestors().skip(1).map(Path::to_path_buf));
+        if change.file_type == FileType::Dir {
+            dirs.insert(change.path.clone());
+        }
+    }
+    for dir in dirs.iter().rev() {
+        let dest = staging.join(dir);
+        if dest.is_dir() {
+            native::copy_dir_metadata(&upper.join(dir), &dest)?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes applied entries from the upper layer, so the disk copies show
+/// through again. Whiteouts of protected files stay: the disk kept them, and
+/// RAM keeps them deleted. Dirs are removed only once empty.
+fn drop_entries(cfg: &ResolvedConfig, applied: &[UpperChange]) -> Result<Vec<PathBuf>> {
+    let upper = upper_dir(cfg);
+    let protect = native::Filter::new(&[], &cfg.raw.sync.protect)?;
+    let mut dropped = Vec::new();
+    let mut dirs = Vec::new();
+    for change in applied {
+        let entry = upper.join(&change.entry);
+        match change.kind {
+            UpperKind::Write => {
+                native::remove_entry(&entry)?;
+                dropped.push(change.path.clone());
+            }
+            UpperKind::Delete => {
+                if protect.is_excluded(&change.path, false) {
+                    continue;
+                }
+                native::remove_entry(&entry)?;
+                dropped.push(change.path.clone());
+            }
+            UpperKind::Opaque => {
+                if entry.file_name().and_then(|n| n.to_str()) == Some(OPAQUE_MARKER) {
+                    native::remove_entry(&entry)?;
+                } else {
+                    for name in OPAQUE_XATTRS {
+                        let _ = xattr::remove(&entry, name);
+                    }
+                }
+                dirs.push(upper.join(&change.path));
+            }
+            UpperKind::Dir => dirs.push(entry),
+        }
+    }
+    dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+    for dir in dirs {
+        if is_empty_dir(&dir)? {
+            native::remove_entry(&dir)?;
+        }
+    }
+    Ok(dropped)
+}
+
+/// Applies the upper layer to disk through the same path as copy mode:
+/// changes are staged, classified against the baseline, journaled, and only
+/// then written, and the baseline follows. The lower layer must not change
+/// under a mounted overlay, so the overlay is unmounted first (which fails
+/// while the workspace is in use) and mounted again afterwards. Applied
+/// entries are dropped from the upper layer in between.
+pub fn sync_back(cfg: &ResolvedConfig, paths: &[PathBuf], opts: &SyncBackOptions) -> Result<()> {
+    ensure_no_session(cfg)?;
+    let layers = if is_mount_point(&cfg.workspace_root) {
+        Some(unmount(cfg)?)
+    } else {
+        None
+    };
+    let result = apply_upper(cfg, paths, opts);
+    if let Some(layers) = layers {
+        remount(cfg, layers).context("synced back, but failed to mount the overlay again")?;
+    }
+    result
+}
+
+fn apply_upper(cfg: &ResolvedConfig, paths: &[PathBuf], opts: &SyncBackOptions) -> Result<()> {
+    journal::recover(cfg)?;
+    let selected: Vec<PathBuf> = paths
+        .iter()
+        .map(|p| join_relative(Path::new(""), p))
+        .collect();
+    let upper = selected_changes(cfg, &selected)?;
+    let changes = disk_changes(cfg, &upper)?;
+    if changes.is_empty() {
+        info!("upper layer has nothing to sync back");
+        return Ok(());
+    }
+    let staging = cfg.staging_dir();
+    if staging.exists() {
+        fs::remove_dir_all(&staging).context("failed to clean staging directory")?;
+    }
+    ensure_dir(&staging)?;
+    stage(cfg, &staging, &changes)?;
+    let mut plans = Vec::new();
+    let mut claimed = BTreeSet::new();
+    for rel in &selected {
+        let Some(path_opts) = options_for_path(cfg, rel)? else {
+            warn!("{} is excluded by its source; skipping", rel.display());
+            continue;
+        };
+        let mut plan_changes = Vec::new();
+        for change in changes.iter().filter(|c| c.path.starts_with(rel)) {
+            // Overlapping paths would otherwise apply the same change twice.
+            if claimed.insert(change.path.clone()) {
+                plan_changes.push(FileChange {
+                    path: change.path.strip_prefix(rel)?.to_path_buf(),
+                    ..change.clone()
+                });
+            }
+        }
+        plans.push(PathPlan {
+            rel: rel.clone(),
+            source: staging.join(rel),
+            dest: cfg.orig_root.join(rel),
+            workspace: staging.join(rel),
+            sparse: true,
+            changes: plan_changes,
+            opts: path_opts,
+        });
+    }
+    let result = apply_back(cfg, &NativeBackend, &staging, &plans, opts);
+    if result.is_ok() || Journal::load(cfg)?.is_none() {
+        fs::remove_dir_all(&staging).ok();
+    }
+    result?;
+    // Unchanged files and conflicts settled for the disk copy are dropped
+    // too, so the baseline catches up with what RAM now shows.
+    let dropped = drop_entries(cfg, &upper)?;
+    if let Some(mut baseline) = Manifest::load(cfg)? {
+        baseline.update_from(&cfg.orig_root, &dropped)?;
+        baseline.save(cfg)?;
+    }
+    debug!("dropped {} entries from the upper layer", dropped.len());
+    Ok(())
+}
+
+/// Records the baseline of a freshly mounted overlay: the project as it
+/// shows through the mount. An upper layer left from before is not empty,
+/// and recording it would pass its edits off as disk content, so such an
+/// overlay goes without one and every upper entry counts as a RAM edit.
+pub fn record_baseline(cfg: &ResolvedConfig) -> Result<()> {
+    if Manifest::location(cfg).exists() || !is_empty_dir(&upper_dir(cfg))? {
+        return Ok(());
+    }
+    Manifest::record(cfg)?.save(cfg)
+}
@@ -0,0 +1,178 @@
+use crate::config::ResolvedConfig;
+use crate::syncer::FileChange;
+use anyhow::{Context, Result};
+use similar::TextDiff;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// Bytes git inspects when deciding whether a blob is binary.
+const BINARY_SNIFF_LEN: usize = 8000;
+
+/// Mask for the file type bits of a git mode.
+const S_IFMT: u32 = 0o170000;
+
+struct Blob {
+    mode: u32,
+    bytes: Vec<u8>,
+}
+
+fn read_blob(path: &Path) -> Result<Option<Blob>> {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to stat {}", path.display())),
+    };
+    if meta.file_type().is_symlink() {
+        let target = fs::read_link(path)
+            .with_context(|| format!("failed to read link {}", path.display()))?;
+        return Ok(Some(Blob {
+            mode: 0o120000,
+            bytes: target.as_os_str().as_bytes().to_vec(),
+        }));
+    }
+    if !meta.is_file() {
+        return Ok(None);
+    }
+    let mode = if meta.permissions().mode() & 0o111 != 0 {
+        0o100755
+    } else {
+        0o100644
+    };
+    let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(Some(Blob { mode, bytes }))
+}
+
+fn as_text(bytes: &[u8]) -> Option<&str> {
+    let sniff = &bytes[..bytes.len().min(BINARY_SNIFF_LEN)];
+    if sniff.contains(&0) {
+        return None;
+    }
+    std::str::from_utf8(bytes).ok()
+}
+
+/// Renders one change as a git-style patch turning the copy under `old_root`
+/// into the copy under `new_root`.
+pub fn file_patch(old_root: &Path, new_root: &Path, change: &FileChange) -> Result<String> {
+    patch_between(
+        &change.path,
+        &old_root.join(&change.path),
+        &new_root.join(&change.path),
+    )
+}
+
+/// Renders a git-style patch from `old` to `new`, labelled with `label`.
+pub fn patch_between(label: &Path, old: &Path, new: &Path) -> Result<String> {
+    let old = read_blob(old)?;
+    let new = read_blob(new)?;
+    match (&old, &new) {
+        // `git apply` only accepts a file/symlink swap as a delete plus an add.
+        (Some(o), Some(n)) if o.mode & S_IFMT != n.mode & S_IFMT => {
+            let mut out = render(label, Some(o), None)?;
+            out.push_str(&render(label, None, Some(n))?);
+            Ok(out)
+        }
+        _ => render(label, old.as_ref(), new.as_ref()),
+    }
+}
+
+fn render(label: &Path, old: Option<&Blob>, new: Option<&Blob>) -> Result<String> {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "diff --git {} {}",
+        quote_path("a/", label),
+        quote_path("b/", label)
+    )?;
+    match (old, new) {
+        (None, None) => return Ok(String::new()),
+        (None, Some(n)) => writeln!(out, "new file mode {:o}", n.mode)?,
+        (Some(o), None) => writeln!(out, "deleted file mode {:o}", o.mode)?,
+        (Some(o), Some(n)) if o.mode != n.mode => {
+            writeln!(out, "old mode {:o}", o.mode)?;
+            writeln!(out, "new mode {:o}", n.mode)?;
+        }
+        _ => {}
+    }
+    let old_name = match old {
+        Some(_) => quote_path("a/", label),
+        None => "/dev/null".to_string(),
+    };
+    let new_name = match new {
+        Some(_) => quote_path("b/", label),
+        None => "/dev/null".to_string(),
+    };
+    let old_bytes = old.map_or(&[][..], |b| &b.bytes);
+    let new_bytes = new.map_or(&[][..], |b| &b.bytes);
+    if old_bytes == new_bytes {
+        return Ok(out);
+    }
+    match (as_text(old_bytes), as_text(new_bytes)) {
+        (Some(a), Some(b)) => {
+            // Like git, end names containing a space with a tab.
+            let header = |name: &str| {
+                if name.contains(' ') {
+                    format!("{name}\t")
+                } else {
+                    name.to_string()
+                }
+            };
+            let diff = TextDiff::from_lines(a, b);
+            write!(
+                out,
+                "{}",
+                diff.unified_diff()
+                    .context_radius(3)
+                    .missing_newline_hint(true)
+                    .header(&header(&old_name), &header(&new_name))
+            )?;
+        }
+        _ => writeln!(out, "Binary files {old_name} and {new_name} differ")?,
+    }
+    Ok(out)
+}
+
+/// Prefixes `path` the way git names it in patch headers, C-quoting it when
+/// it holds a quote, a backslash, a control character or non-ASCII bytes.
+fn quote_path(prefix: &str, path: &Path) -> String {
+    let bytes = path.as_os_str().as_bytes();
+    if !bytes
+        .iter()
+        .any(|&b| b == b'"' || b == b'\\' || !(0x20..0x7f).contains(&b))
+    {
+        return format!("{prefix}{}", path.display());
+    }
+    let mut quoted = format!("\"{prefix}");
+    for &b in bytes {
+        match b {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            0x0b => quoted.push_str("\\v"),
+            0x0c => quoted.push_str("\\f"),
+            b'\r' => quoted.push_str("\\r"),
+            b if !(0x20..0x7f).contains(&b) => {
+                let _ = write!(quoted, "\\{b:03o}");
+            }
+            b => quoted.push(b as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Renders project-relative changes as a patch that `git apply` can replay on
+/// the disk copy to reproduce the RAM copy.
+pub fn render_patch(cfg: &ResolvedConfig, changes: &[FileChange]) -> Result<String> {
+    let mut out = String::new();
+    for change in changes {
+        out.push_str(&file_patch(&cfg.orig_root, &cfg.workspace_root, change)?);
+    }
+    Ok(out)
+}
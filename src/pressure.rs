@@ -0,0 +1,228 @@
+use crate::config::{BuildDirType, PressureConfig, ResolvedConfig, SpillPolicy};
+use crate::native::NativeBackend;
+use crate::syncer::{sync_path, SyncOptions};
+use crate::util::{dir_size, ensure_dir, format_bytes};
+use anyhow::{Context, Result};
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// One reading of `/proc/pressure/memory` and `/proc/meminfo`. Fields are
+/// `None` when the kernel does not provide them.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sample {
+    /// Share of the last 10s in which some task stalled on memory.
+    pub some_avg10: Option<f64>,
+    pub mem_total: Option<u64>,
+    pub mem_available: Option<u64>,
+}
+
+impl Sample {
+    pub fn read() -> Self {
+        let psi = fs::read_to_string("/proc/pressure/memory").unwrap_or_default();
+        let meminfo = fs::read_to_string("/proc/meminfo").unwrap_or_default();
+        Self::parse(&psi, &meminfo)
+    }
+
+    pub fn parse(psi: &str, meminfo: &str) -> Self {
+        let some_avg10 = psi
+            .lines()
+            .find_map(|line| line.strip_prefix("some "))
+            .and_then(|fields| {
+                fields
+                    .split_whitespace()
+                    .find_map(|f| f.strip_prefix("avg10="))
+            })
+            .and_then(|v| v.parse().ok());
+        let kib = |key: &str| {
+            meminfo.lines().find_map(|line| {
+                let value = line.strip_prefix(key)?.strip_prefix(':')?;
+                let kib: u64 = value.trim().trim_end_matches("kB").trim().parse().ok()?;
+                Some(kib * 1024)
+            })
+        };
+        Sample {
+            some_avg10,
+            mem_total: kib("MemTotal"),
+            mem_available: kib("MemAvailable"),
+        }
+    }
+
+    fn available_percent(&self) -> Option<f64> {
+        match (self.mem_available, self.mem_total) {
+            (Some(available), Some(total)) if total > 0 => {
+                Some(available as f64 * 100.0 / total as f64)
+            }
+            _ => None,
+        }
+    }
+
+    /// PSI above `threshold`, or available memory below `min_available`.
+    pub fn is_high(&self, cfg: &PressureConfig) -> bool {
+        self.some_avg10.is_some_and(|p| p >= cfg.threshold)
+            || self
+                .available_percent()
+                .is_some_and(|p| p < cfg.min_available)
+    }
+
+    fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(psi) = self.some_avg10 {
+            parts.push(format!("PSI some avg10 {psi:.1}%"));
+        }
+        if let (Some(available), Some(percent)) = (self.mem_available, self.available_percent()) {
+            parts.push(format!(
+                "{} available ({percent:.0}%)",
+                format_bytes(available)
+            ));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Tracks how long pressure has been high and fires once per `sustain`
+/// period while it stays that way.
+#[derive(Debug, Default)]
+pub struct Monitor {
+    high_since: Option<Instant>,
+}
+
+impl Monitor {
+    pub fn observe(&mut self, high: bool, now: Instant, sustain: Duration) -> bool {
+        if !high {
+            self.high_since = None;
+            return false;
+        }
+        let since = *self.high_since.get_or_insert(now);
+        if now.duration_since(since) >= sustain {
+            self.high_since = Some(now);
+            return true;
+        }
+        false
+    }
+}
+
+/// Moves the largest scratch build dir that is still in RAM to the spill dir
+/// on disk and leaves a symlink in its place. Returns the moved dir and its
+/// size, or `None` if there was nothing to move.
+///
+/// Builds may still be writing to the dir, so it is copied in place first and
+/// only then swapped for the symlink, leaving the path missing for a single
+/// rename. Writes that landed during the first copy are caught up from the
+/// renamed dir before it is removed. If the swap fails the dir stays in RAM.
+pub fn spill_largest(cfg: &ResolvedConfig) -> Result<Option<(PathBuf, u64)>> {
+    let largest = cfg
+        .raw
+        .build_dirs
+        .iter()
+        .filter(|b| b.r#type == BuildDirType::Scratch)
+        .filter(|b| {
+            fs::symlink_metadata(cfg.workspace_root.join(&b.path)).is_ok_and(|m| m.is_dir())
+        })
+        .map(|b| (b, dir_size(&cfg.workspace_root.join(&b.path))))
+        .filter(|(_, size)| *size > 0)
+        .max_by_key(|(_, size)| *size);
+    let Some((build, size)) = largest else {
+        return Ok(None);
+    };
+    let ram = cfg.workspace_root.join(&build.path);
+    let disk = cfg.spill_dir().join(&build.path);
+    if disk.exists() {
+        fs::remove_dir_all(&disk).with_context(|| format!("failed to clear {}", disk.display()))?;
+    }
+    if let Some(parent) = disk.parent() {
+        ensure_dir(parent)?;
+    }
+    // The spill dir is normally on another filesystem, so rename rarely works.
+    if fs::rename(&ram, &disk).is_ok() {
+        if let Err(e) = symlink(&disk, &ram) {
+            let _ = fs::rename(&disk, &ram);
+            return Err(e).with_context(|| format!("failed to link {}", ram.display()));
+        }
+        return Ok(Some((build.path.clone(), size)));
+    }
+    let opts = SyncOptions {
+        delete: true,
+        ..SyncOptions::from_config(&cfg.raw.sync)
+    };
+    let copy = |from: &Path| sync_path(&NativeBackend, from, &disk, &opts);
+    if let Err(e) = copy(&ram) {
+        let _ = fs::remove_dir_all(&disk);
+        return Err(e);
+    }
+    let mut parked = ram.clone().into_os_string();
+    parked.push(".ramws-spill");
+    let parked = PathBuf::from(parked);
+    fs::rename(&ram, &parked).with_context(|| format!("failed to move {}", ram.display()))?;
+    if let Err(e) = symlink(&disk, &ram) {
+        // Something recreated the dir in the meantime; keep building in RAM.
+        let _ = fs::rename(&parked, &ram);
+        let _ = fs::remove_dir_all(&disk);
+        return Err(e).with_context(|| format!("failed to link {}", ram.display()));
+    }
+    copy(&parked)?;
+    fs::remove_dir_all(&parked)
+        .with_context(|| format!("failed to remove {}", parked.display()))?;
+    Ok(Some((build.path.clone(), size)))
+}
+
+fn on_high_pressure(cfg: &ResolvedConfig, sample: &Sample) {
+    eprintln!(
+        "Warning: memory pressure has stayed high for {}s ({}); the workspace holds {}",
+        cfg.raw.pressure.sustain,
+        sample.describe(),
+        format_bytes(dir_size(&cfg.workspace_root))
+    );
+    if cfg.raw.pressure.spill != SpillPolicy::LargestScratch {
+        return;
+    }
+    match spill_largest(cfg) {
+        Ok(Some((path, size))) => eprintln!(
+            "ramws: moved scratch dir {} ({}) to {}",
+            path.display(),
+            format_bytes(size),
+            cfg.spill_dir().join(&path).display()
+        ),
+        Ok(None) => debug!("no scratch dir left in RAM to spill"),
+        Err(e) => eprintln!("Warning: failed to spill scratch dir: {e:#}"),
+    }
+}
+
+/// Samples memory pressure every `pressure.interval` seconds on its own
+/// thread.
+pub struct BackgroundPressure {
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl BackgroundPressure {
+    /// `None` when monitoring is disabled.
+    pub fn spawn(cfg: ResolvedConfig) -> Option<Self> {
+        if !cfg.raw.pressure.enabled {
+            return None;
+        }
+        let (stop, rx) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            let settings = &cfg.raw.pressure;
+            let interval = Duration::from_secs(settings.interval.max(1));
+            let sustain = Duration::from_secs(settings.sustain);
+            let mut monitor = Monitor::default();
+            while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(interval) {
+                let sample = Sample::read();
+                if monitor.observe(sample.is_high(settings), Instant::now(), sustain) {
+                    on_high_pressure(&cfg, &sample);
+                }
+            }
+        });
+        Some(BackgroundPressure { stop, handle })
+    }
+
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.handle.join();
+    }
+}
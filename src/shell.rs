@@ -1,7 +1,12 @@
-use crate::workspace::Workspace;
+use crate::checkpoint::BackgroundCheckpoint;
+use crate::mount;
+use crate::pressure::BackgroundPressure;
+use crate::watch::{self, BackgroundWatch};
+use crate::workspace::{StartOptions, Workspace};
 use anyhow::{Context, Result};
 use std::env;
 use std::process::Command;
+use std::time::Duration;
 use tracing::info;
 
 pub struct ShellOptions {
@@ -9,10 +14,20 @@ pub struct ShellOptions {
     pub no_prompt: bool,
     pub noninteractive: bool,
     pub command: Vec<String>,
+    /// Start even when `git.require_clean` finds uncommitted changes.
+    pub ignore_dirty: bool,
 }
 
 pub fn run_shell(workspace: &Workspace, opts: ShellOptions) -> Result<i32> {
-    workspace.ensure(false)?;
+    if workspace.config.raw.sync.continuous {
+        watch::ensure_watchable(&workspace.config)?;
+    }
+    // Has to come first: entering a namespace needs a single-threaded process.
+    mount::mount_workspace(&workspace.config, true)?;
+    workspace.ensure(&StartOptions {
+        ignore_dirty: opts.ignore_dirty,
+        ..StartOptions::default()
+    })?;
     let ws_root = workspace.config.workspace_root.clone();
     let shell_bin = opts
         .shell
@@ -45,7 +60,26 @@ pub fn run_shell(workspace: &Workspace, opts: ShellOptions) -> Result<i32> {
             cmd.env("PS1", format!("{} \\\\u$ ", prefix));
         }
     }
+    let watcher = workspace
+        .config
+        .raw
+        .sync
+        .continuous
+        .then(|| BackgroundWatch::spawn(workspace.config.clone()));
+    let checkpoints = workspace.config.raw.checkpoint.interval.map(|secs| {
+        BackgroundCheckpoint::spawn(workspace.config.clone(), Duration::from_secs(secs))
+    });
+    let pressure = BackgroundPressure::spawn(workspace.config.clone());
     info!("launching shell in {}", ws_root.display());
-    let status = cmd.status().context("failed to launch shell")?;
-    Ok(status.code().unwrap_or(1))
+    let status = cmd.status().context("failed to launch shell");
+    if let Some(pressure) = pressure {
+        pressure.stop();
+    }
+    if let Some(checkpoints) = checkpoints {
+        checkpoints.stop();
+    }
+    if let Some(watcher) = watcher {
+        watcher.stop()?;
+    }
+    Ok(status?.code().unwrap_or(1))
 }
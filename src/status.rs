@@ -1,6 +1,12 @@
+use crate::budget;
 use crate::config::{ResolvedConfig, SyncOnExit};
-use crate::syncer::{diff_path, SyncOptions};
+use crate::journal::Journal;
+use crate::lazy::LazySet;
+use crate::mount::is_mount_point;
+use crate::overlay;
+use crate::syncer::{pending_changes, DiffSummary};
 use crate::util::{format_bytes, fs_status};
+use crate::workspace::{CacheStatus, Workspace};
 use anyhow::Result;
 use serde::Serialize;
 
@@ -12,11 +18,27 @@ pub struct StatusReport {
     pub total: Option<String>,
     pub available: Option<String>,
     pub used: Option<String>,
+    /// Set when `workspace.mount` is configured.
+    pub mount: Option<MountStatus>,
     pub diff_changed: usize,
     pub diff_added: usize,
     pub diff_deleted: usize,
     pub sync_policy: SyncOnExit,
     pub config_path: String,
+    pub caches: Vec<CacheStatus>,
+    pub warnings: Vec<String>,
+    /// Files a lazy start has not copied into RAM yet.
+    pub lazy_pending: usize,
+}
+
+/// The private tmpfs and how much of its limits is in use.
+#[derive(Debug, Serialize)]
+pub struct MountStatus {
+    pub mounted: bool,
+    pub size: Option<String>,
+    pub nr_inodes: Option<String>,
+    pub inodes_used: Option<u64>,
+    pub inodes_total: Option<u64>,
 }
 
 pub fn collect_status(cfg: &ResolvedConfig) -> Result<StatusReport> {
@@ -25,35 +47,45 @@ pub fn collect_status(cfg: &ResolvedConfig) -> Result<StatusReport> {
     let mut total = None;
     let mut available = None;
     let mut used = None;
+    let mut mount = cfg.raw.workspace.mount.as_ref().map(|spec| MountStatus {
+        mounted: exists && is_mount_point(&cfg.workspace_root),
+        size: spec.size.as_ref().map(ToString::to_string),
+        nr_inodes: spec.nr_inodes.as_ref().map(ToString::to_string),
+        inodes_used: None,
+        inodes_total: None,
+    });
     if exists {
         if let Ok(stat) = fs_status(&cfg.workspace_root) {
             fs_type = Some(stat.fs_type);
             total = Some(format_bytes(stat.total));
             available = Some(format_bytes(stat.available));
             used = Some(format_bytes(stat.used));
-        }
-    }
-    let mut diff_changed = 0usize;
-    let mut diff_added = 0usize;
-    let mut diff_deleted = 0usize;
-    if exists {
-        for source in &cfg.raw.sources {
-            let ws_path = cfg.workspace_root.join(&source.path);
-            let orig_path = cfg.orig_root.join(&source.path);
-            let opts = SyncOptions {
-                delete: cfg.raw.sync.delete,
-                include: source.include.clone(),
-                exclude: source.exclude.clone(),
-                itemize: true,
-                dry_run: true,
-            };
-            if let Ok(summary) = diff_path(&ws_path, &orig_path, opts) {
-                diff_changed += summary.changed;
-                diff_added += summary.added;
-                diff_deleted += summary.deleted;
+            if let Some(mount) = mount.as_mut().filter(|m| m.mounted) {
+                mount.inodes_used = Some(stat.inodes - stat.inodes_free);
+                mount.inodes_total = Some(stat.inodes);
             }
         }
     }
+    let summary = if exists {
+        DiffSummary::from_changes(&pending_changes(cfg)?)
+    } else {
+        DiffSummary::default()
+    };
+    let mut warnings = budget::status_warnings(cfg);
+    if exists && overlay::is_unmounted(cfg) {
+        warnings.push(format!(
+            "the overlay is not mounted at {}; the changes above are still in its upper layer \
+             (run ramws start to mount it again, or ramws sync --back to apply them)",
+            cfg.workspace_root.display()
+        ));
+    }
+    if Journal::location(cfg).exists() {
+        warnings.push(
+            "a sync back was interrupted; the next start, shell, sync, watch or undo \
+             finishes or undoes it"
+                .to_string(),
+        );
+    }
     Ok(StatusReport {
         workspace_exists: exists,
         workspace_root: cfg.workspace_root.display().to_string(),
@@ -61,10 +93,14 @@ pub fn collect_status(cfg: &ResolvedConfig) -> Result<StatusReport> {
         total,
         available,
         used,
-        diff_changed,
-        diff_added,
-        diff_deleted,
+        mount,
+        diff_changed: summary.changed,
+        diff_added: summary.added,
+        diff_deleted: summary.deleted,
         sync_policy: cfg.raw.sync.on_exit.clone(),
         config_path: cfg.config_path.display().to_string(),
+        caches: Workspace::new(cfg.clone()).cache_sizes(),
+        warnings,
+        lazy_pending: LazySet::current(cfg)?.pending.len(),
     })
 }
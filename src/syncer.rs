@@ -1,48 +1,152 @@
-use crate::config::{BuildDirType, ResolvedConfig};
-use crate::util::{path_with_trailing_slash, prompt_confirm};
+use crate::backup::{self, Backup};
+use crate::config::{
+    BuildDirType, DeleteLimit, GitMode, ResolvedConfig, SourceSelect, SourceSpec, SyncBackendKind,
+    SyncConfig, WorkspaceMode,
+};
+use crate::conflict::{
+    classify, keep_both, keep_both_paths, resolve, ChangeOrigin, Conflict, Resolution,
+};
+use crate::git;
+use crate::ignore_files::IgnoreFiles;
+use crate::journal::{self, Journal, JournalPlan};
+use crate::lazy::LazySet;
+use crate::manifest::Manifest;
+use crate::native::{self, NativeBackend};
+use crate::overlay;
+use crate::util::{
+    find_in_path, is_empty_dir, join_relative, path_with_trailing_slash, prompt_confirm,
+};
 use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
-use tracing::info;
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum SyncDirection {
-    OrigToWorkspace,
-    WorkspaceToOrig,
-}
+use std::process::{Command, Stdio};
+use tracing::{debug, info, warn};
 
 #[derive(Debug, Clone)]
 pub struct SyncOptions {
     pub delete: bool,
     pub include: Vec<String>,
     pub exclude: Vec<String>,
-    pub itemize: bool,
-    pub dry_run: bool,
+    pub checksum: bool,
+    /// The owning source's `select`, when it is not `all`.
+    pub select: Option<git::Selector>,
+    /// Ignore files to honour, for sources with `ignore_files` set.
+    pub ignore_files: Option<IgnoreFiles>,
+}
+
+impl SyncOptions {
+    pub fn from_config(sync: &SyncConfig) -> Self {
+        SyncOptions {
+            delete: sync.delete,
+            include: vec![],
+            exclude: vec![],
+            checksum: sync.checksum,
+            select: None,
+            ignore_files: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// One entry that differs between a sync source and its destination. Paths are
+/// relative to the roots passed to the backend.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+    pub file_type: FileType,
+    pub size_delta: i64,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct DiffSummary {
     pub changed: usize,
     pub added: usize,
     pub deleted: usize,
 }
 
-fn build_rsync_command(
-    source: &Path,
-    dest: &Path,
-    direction: SyncDirection,
-    opts: &SyncOptions,
-) -> Command {
-    let mut cmd = Command::new("rsync");
-    cmd.arg("-a");
-    if opts.delete {
-        cmd.arg("--delete");
+impl DiffSummary {
+    pub fn from_changes(changes: &[FileChange]) -> Self {
+        let mut summary = DiffSummary::default();
+        for change in changes.iter().filter(|c| c.file_type != FileType::Dir) {
+            match change.kind {
+                ChangeKind::Added => summary.added += 1,
+                ChangeKind::Modified => summary.changed += 1,
+                ChangeKind::Deleted => summary.deleted += 1,
+            }
+        }
+        summary
     }
-    if opts.dry_run {
-        cmd.arg("--dry-run");
+}
+
+/// A way of mirroring one tree onto another. `plan` must not modify anything;
+/// `apply` performs a (possibly filtered) plan, so callers can drop entries
+/// they do not want touched.
+pub trait SyncBackend {
+    fn name(&self) -> &'static str;
+
+    fn plan(&self, source: &Path, dest: &Path, opts: &SyncOptions) -> Result<Vec<FileChange>>;
+
+    fn apply(
+        &self,
+        source: &Path,
+        dest: &Path,
+        changes: &[FileChange],
+        opts: &SyncOptions,
+    ) -> Result<()>;
+
+    /// Content differences between the trees; directory entries are left out.
+    fn diff(&self, source: &Path, dest: &Path, opts: &SyncOptions) -> Result<Vec<FileChange>> {
+        Ok(self
+            .plan(source, dest, opts)?
+            .into_iter()
+            .filter(|c| c.file_type != FileType::Dir)
+            .collect())
     }
-    if opts.itemize {
-        cmd.arg("--itemize-changes");
+}
+
+pub fn backend_for(kind: SyncBackendKind) -> Box<dyn SyncBackend> {
+    match kind {
+        SyncBackendKind::Native => Box::new(NativeBackend),
+        SyncBackendKind::Rsync => Box::new(RsyncBackend),
+        SyncBackendKind::Auto => {
+            if find_in_path("rsync").is_some() {
+                Box::new(RsyncBackend)
+            } else {
+                Box::new(NativeBackend)
+            }
+        }
+    }
+}
+
+/// Backend that shells out to `rsync -a`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RsyncBackend;
+
+fn build_rsync_command(source: &Path, dest: &Path, opts: &SyncOptions) -> Command {
+    let mut cmd = Command::new("rsync");
+    cmd.arg("-a");
+    if opts.checksum {
+        cmd.arg("--checksum");
     }
     for inc in &opts.include {
         cmd.arg(format!("--include={inc}"));
@@ -50,112 +154,747 @@ fn build_rsync_command(
     for exc in &opts.exclude {
         cmd.arg(format!("--exclude={exc}"));
     }
-    let mut src = source.to_path_buf();
-    let dst = dest.to_path_buf();
-    match direction {
-        SyncDirection::OrigToWorkspace => {
-            src = PathBuf::from(path_with_trailing_slash(&src));
+    cmd.arg("--exclude=/.ramws/");
+    cmd.arg("--exclude=/.ramws-staging/");
+    cmd.arg(path_with_trailing_slash(source));
+    cmd.arg(dest);
+    cmd
+}
+
+fn run_rsync(mut cmd: Command, stdin: Option<&[u8]>) -> Result<String> {
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    if stdin.is_some() {
+        cmd.stdin(Stdio::piped());
+    }
+    let mut child = cmd.spawn().context("failed to run rsync")?;
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(input).context("failed to feed rsync")?;
+    }
+    let output = child.wait_with_output().context("failed to run rsync")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("rsync failed: {stderr}");
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parses one line of `rsync --itemize-changes` output.
+pub fn parse_itemize_line(line: &str) -> Option<(ChangeKind, FileType, PathBuf)> {
+    if let Some(rest) = line.strip_prefix("*deleting") {
+        let path = rest.trim_start();
+        let file_type = if path.ends_with('/') {
+            FileType::Dir
+        } else {
+            FileType::File
+        };
+        return Some((
+            ChangeKind::Deleted,
+            file_type,
+            PathBuf::from(path.trim_end_matches('/')),
+        ));
+    }
+    let (flags, path) = line.split_once(' ')?;
+    if flags.len() != 11 || !flags.is_ascii() {
+        return None;
+    }
+    let path = path.split(" -> ").next()?.trim_end_matches('/');
+    if path.is_empty() || path == "." {
+        return None;
+    }
+    let file_type = match flags.as_bytes()[1] {
+        b'd' => FileType::Dir,
+        b'L' => FileType::Symlink,
+        _ => FileType::File,
+    };
+    let kind = if flags[2..].chars().all(|c| c == '+') {
+        ChangeKind::Added
+    } else {
+        ChangeKind::Modified
+    };
+    Some((kind, file_type, PathBuf::from(path)))
+}
+
+fn entry_size(path: &Path) -> i64 {
+    fs::symlink_metadata(path)
+        .map(|m| m.len() as i64)
+        .unwrap_or(0)
+}
+
+impl SyncBackend for RsyncBackend {
+    fn name(&self) -> &'static str {
+        "rsync"
+    }
+
+    fn plan(&self, source: &Path, dest: &Path, opts: &SyncOptions) -> Result<Vec<FileChange>> {
+        let mut cmd = build_rsync_command(source, dest, opts);
+        cmd.args(["--dry-run", "--itemize-changes"]);
+        if opts.delete {
+            cmd.arg("--delete");
         }
-        SyncDirection::WorkspaceToOrig => {
-            src = PathBuf::from(path_with_trailing_slash(&src));
+        let stdout = run_rsync(cmd, None)?;
+        // rsync knows nothing of `select` or ignore files; its plan is
+        // narrowed afterwards.
+        let narrow = if opts.select.is_some() || opts.ignore_files.is_some() {
+            Some(native::Filter::for_options(opts)?)
+        } else {
+            None
+        };
+        let mut changes = Vec::new();
+        for line in stdout.lines() {
+            let Some((kind, file_type, path)) = parse_itemize_line(line) else {
+                continue;
+            };
+            if narrow
+                .as_ref()
+                .is_some_and(|f| f.excludes_path(&path, file_type == FileType::Dir))
+            {
+                continue;
+            }
+            let size_delta = if file_type == FileType::Dir {
+                0
+            } else {
+                let old = match kind {
+                    ChangeKind::Added => 0,
+                    _ => entry_size(&dest.join(&path)),
+                };
+                let new = match kind {
+                    ChangeKind::Deleted => 0,
+                    _ => entry_size(&source.join(&path)),
+                };
+                new - old
+            };
+            changes.push(FileChange {
+                path,
+                kind,
+                file_type,
+                size_delta,
+            });
         }
+        Ok(changes)
+    }
+
+    fn apply(
+        &self,
+        source: &Path,
+        dest: &Path,
+        changes: &[FileChange],
+        opts: &SyncOptions,
+    ) -> Result<()> {
+        let mut deletes: Vec<&FileChange> = changes
+            .iter()
+            .filter(|c| c.kind == ChangeKind::Deleted)
+            .collect();
+        deletes.sort_by(|a, b| b.path.cmp(&a.path));
+        for change in deletes {
+            native::remove_entry(&dest.join(&change.path))?;
+        }
+        let mut list = Vec::new();
+        for change in changes.iter().filter(|c| c.kind != ChangeKind::Deleted) {
+            list.extend_from_slice(change.path.as_os_str().as_bytes());
+            list.push(0);
+        }
+        if list.is_empty() {
+            return Ok(());
+        }
+        let mut cmd = build_rsync_command(source, dest, opts);
+        cmd.args(["--from0", "--files-from=-"]);
+        run_rsync(cmd, Some(&list))?;
+        Ok(())
     }
-    cmd.arg(src);
-    cmd.arg(dst);
-    cmd
 }
 
 pub fn sync_path(
+    backend: &dyn SyncBackend,
     source: &Path,
     dest: &Path,
-    direction: SyncDirection,
-    opts: SyncOptions,
+    opts: &SyncOptions,
 ) -> Result<()> {
-    let mut cmd = build_rsync_command(source, dest, direction, &opts);
-    let output = cmd.output().context("failed to run rsync")?;
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("rsync failed: {stderr}");
+    let changes = backend.plan(source, dest, opts)?;
+    debug!(
+        "{}: {} changes from {} to {}",
+        backend.name(),
+        changes.len(),
+        source.display(),
+        dest.display()
+    );
+    backend.apply(source, dest, &changes, opts)
+}
+
+pub fn diff_path(
+    backend: &dyn SyncBackend,
+    source: &Path,
+    dest: &Path,
+    opts: &SyncOptions,
+) -> Result<DiffSummary> {
+    Ok(DiffSummary::from_changes(
+        &backend.diff(source, dest, opts)?,
+    ))
+}
+
+/// Sync options for a whole source: its own filters plus anchored excludes
+/// for build dirs below it, which live in RAM only (or in their cache store).
+pub fn source_options(cfg: &ResolvedConfig, source: &SourceSpec) -> SyncOptions {
+    let root = join_relative(Path::new(""), &source.path);
+    let mut exclude = source.exclude.clone();
+    if cfg.raw.git.mode == GitMode::Worktree && root.as_os_str().is_empty() {
+        // The worktree's `.git` file in RAM and the repository on disk.
+        exclude.push("/.git".to_string());
+        exclude.push("/.git/**".to_string());
+    }
+    for build in &cfg.raw.build_dirs {
+        let path = join_relative(Path::new(""), &build.path);
+        if let Ok(sub) = path.strip_prefix(&root) {
+            if !sub.as_os_str().is_empty() {
+                // The bare path also covers the symlink left by `link: true`.
+                exclude.push(format!("/{}", sub.display()));
+                exclude.push(format!("/{}/**", sub.display()));
+            }
+        }
+    }
+    let select = (source.select != SourceSelect::All).then(|| git::Selector {
+        mode: source.select,
+        disk: cfg.orig_root.join(&source.path),
+        ram: cfg.workspace_root.join(&source.path),
+    });
+    let ignore_files = source.ignore_files.then(|| IgnoreFiles {
+        disk: cfg.orig_root.join(&source.path),
+        project: cfg.orig_root.clone(),
+        git: source.select == SourceSelect::All,
+    });
+    SyncOptions {
+        include: source.include.clone(),
+        exclude,
+        select,
+        ignore_files,
+        ..SyncOptions::from_config(&cfg.raw.sync)
     }
-    Ok(())
 }
 
-pub fn diff_path(source: &Path, dest: &Path, opts: SyncOptions) -> Result<DiffSummary> {
-    let mut cmd = build_rsync_command(source, dest, SyncDirection::WorkspaceToOrig, &opts);
-    let output = cmd.output().context("failed to run rsync")?;
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("rsync failed: {stderr}");
+/// Files that differ between the RAM copy and disk across all configured
+/// sources, with paths relative to the project root.
+pub fn pending_changes(cfg: &ResolvedConfig) -> Result<Vec<FileChange>> {
+    if cfg.raw.workspace.mode == WorkspaceMode::Overlay {
+        return overlay::pending_changes(cfg);
     }
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut summary = DiffSummary::default();
-    for line in stdout.lines() {
-        if line.starts_with(">f") || line.starts_with(".f") || line.starts_with("cD") {
-            summary.changed += 1;
-        } else if line.starts_with(">f+++++++++") {
-            summary.added += 1;
-        } else if line.starts_with("*deleting") {
-            summary.deleted += 1;
+    let backend = backend_for(cfg.raw.sync.backend);
+    let lazy = LazySet::current(cfg)?;
+    let mut changes: Vec<FileChange> = Vec::new();
+    for source in &cfg.raw.sources {
+        let opts = source_options(cfg, source);
+        let ws_path = cfg.workspace_root.join(&source.path);
+        let orig_path = cfg.orig_root.join(&source.path);
+        for change in backend.diff(&ws_path, &orig_path, &opts)? {
+            let path = join_relative(&source.path, &change.path);
+            if change.kind == ChangeKind::Deleted && lazy.is_pending(&path) {
+                continue;
+            }
+            changes.push(FileChange { path, ..change });
         }
     }
-    Ok(summary)
+    // Overlapping sources would otherwise report the same file twice.
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes.dedup_by(|a, b| a.path == b.path);
+    Ok(changes)
 }
 
-pub fn sync_back(cfg: &ResolvedConfig, paths: &[PathBuf], noninteractive: bool) -> Result<()> {
-    let staging = cfg.orig_root.join(".ramws-staging");
+/// Sync options for the project-relative path `rel`: build dirs are mirrored
+/// whole, anything else takes the filters of the source that owns it, rebased
+/// onto `rel`. Returns `None` when `rel` itself is excluded by its source.
+pub fn options_for_path(cfg: &ResolvedConfig, rel: &Path) -> Result<Option<SyncOptions>> {
+    let rel = join_relative(Path::new(""), rel);
+    let base = SyncOptions::from_config(&cfg.raw.sync);
+    if cfg
+        .raw
+        .build_dirs
+        .iter()
+        .any(|b| rel.starts_with(join_relative(Path::new(""), &b.path)))
+    {
+        return Ok(Some(base));
+    }
+    let owner = cfg
+        .raw
+        .sources
+        .iter()
+        .map(|s| (join_relative(Path::new(""), &s.path), s))
+        .filter(|(root, _)| rel.starts_with(root))
+        .max_by_key(|(root, _)| root.components().count());
+    let Some((root, source)) = owner else {
+        return Ok(Some(base));
+    };
+    let sub = rel.strip_prefix(&root)?;
+    let source_opts = source_options(cfg, source);
+    // Membership of `select` is checked below, against the narrower root.
+    let filter = native::Filter::for_options(&SyncOptions {
+        select: None,
+        ..source_opts.clone()
+    })?;
+    if filter.excludes_path(sub, cfg.workspace_root.join(&rel).is_dir()) {
+        return Ok(None);
+    }
+    let opts = SyncOptions {
+        include: native::rebase_patterns(&source_opts.include, sub),
+        exclude: native::rebase_patterns(&source_opts.exclude, sub),
+        select: source_opts.select.map(|s| s.rebase(sub)),
+        ignore_files: source_opts.ignore_files.map(|i| i.rebase(sub)),
+        ..base
+    };
+    if opts.select.is_some() {
+        let is_dir = cfg.workspace_root.join(&rel).is_dir() || cfg.orig_root.join(&rel).is_dir();
+        if native::Filter::for_options(&opts)?.is_excluded(Path::new(""), is_dir) {
+            return Ok(None);
+        }
+    }
+    Ok(Some(opts))
+}
+
+/// Changes planned for one synced path; change paths are relative to `rel`.
+pub(crate) struct PathPlan {
+    pub rel: PathBuf,
+    pub source: PathBuf,
+    pub dest: PathBuf,
+    /// The live RAM copy of `rel`, which conflict tools edit.
+    pub workspace: PathBuf,
+    /// `source` holds only the changed entries (the overlay upper layer), so
+    /// an empty or missing source says nothing about deletions.
+    pub sparse: bool,
+    pub changes: Vec<FileChange>,
+    pub opts: SyncOptions,
+}
+
+/// Knobs for a single sync back run.
+#[derive(Debug, Clone, Default)]
+pub struct SyncBackOptions {
+    pub noninteractive: bool,
+    /// Proceed even when the deletions exceed `sync.max_delete`.
+    pub allow_mass_delete: bool,
+    /// Overwrite files with uncommitted changes despite `git.require_clean`.
+    pub ignore_dirty: bool,
+    /// Fold this run's backup into the watch session backup with this id
+    /// instead of keeping (and pruning) one backup per run.
+    pub session_backup: Option<String>,
+}
+
+pub fn sync_back(cfg: &ResolvedConfig, paths: &[PathBuf], opts: &SyncBackOptions) -> Result<()> {
+    if cfg.raw.workspace.mode == WorkspaceMode::Overlay {
+        return overlay::sync_back(cfg, paths, opts);
+    }
+    journal::recover(cfg)?;
+    let staging = cfg.staging_dir();
     if staging.exists() {
         std::fs::remove_dir_all(&staging).context("failed to clean staging directory")?;
     }
     std::fs::create_dir_all(&staging).context("failed to create staging directory")?;
-    let delete = cfg.raw.sync.delete;
-    let mut total_synced = 0usize;
+    let backend = backend_for(cfg.raw.sync.backend);
+    let lazy = LazySet::current(cfg)?;
+    let mut plans = Vec::new();
     for rel in paths {
+        let Some(opts) = options_for_path(cfg, rel)? else {
+            warn!("{} is excluded by its source; skipping", rel.display());
+            continue;
+        };
         let ws_path = cfg.workspace_root.join(rel);
         let stage_path = staging.join(rel);
         if let Some(parent) = stage_path.parent() {
             std::fs::create_dir_all(parent)?;
         }
-        let opts = SyncOptions {
-            delete,
-            include: vec![],
-            exclude: vec![],
-            itemize: false,
-            dry_run: false,
-        };
-        sync_path(
-            &ws_path,
-            &stage_path,
-            SyncDirection::WorkspaceToOrig,
-            opts.clone(),
-        )?;
-        let opts2 = SyncOptions {
-            dry_run: false,
-            ..opts
-        };
+        // Freeze the RAM side first so edits made while syncing cannot leak
+        // into a half-applied plan.
+        sync_path(backend.as_ref(), &ws_path, &stage_path, &opts)?;
         let dest = cfg.orig_root.join(rel);
-        sync_path(&stage_path, &dest, SyncDirection::OrigToWorkspace, opts2)?;
-        total_synced += 1;
+        // Files a lazy start never copied in are missing from RAM without
+        // having been deleted there.
+        let changes = backend
+            .plan(&stage_path, &dest, &opts)?
+            .into_iter()
+            .filter(|c| {
+                c.kind != ChangeKind::Deleted || !lazy.is_pending(&join_relative(rel, &c.path))
+            })
+            .collect();
+        plans.push(PathPlan {
+            rel: rel.clone(),
+            source: stage_path,
+            dest,
+            workspace: ws_path,
+            sparse: false,
+            changes,
+            opts,
+        });
+    }
+    let result = apply_back(cfg, backend.as_ref(), &staging, &plans, opts);
+    if result.is_ok() {
+        lazy.save(cfg)?;
+    }
+    // A journal left behind means disk writes failed midway; keep staging so
+    // the next run can roll forward.
+    if result.is_ok() || Journal::load(cfg)?.is_none() {
+        std::fs::remove_dir_all(&staging).ok();
+    }
+    result
+}
+
+/// Classifies every planned change against the baseline, resolves conflicts,
+/// and only then touches the disk, so a refused sync leaves it unchanged.
+/// Disk writes always go through the native engine's temp-file-and-rename
+/// path under a journal, whichever backend froze the RAM side.
+pub(crate) fn apply_back(
+    cfg: &ResolvedConfig,
+    backend: &dyn SyncBackend,
+    staging: &Path,
+    plans: &[PathPlan],
+    opts: &SyncBackOptions,
+) -> Result<()> {
+    let mut baseline = Manifest::load(cfg)?;
+    let protect = native::Filter::new(&[], &cfg.raw.sync.protect)?;
+    let mut accepted: Vec<Vec<FileChange>> = Vec::new();
+    let mut conflicts: Vec<(usize, &FileChange)> = Vec::new();
+    let mut disk_only = 0usize;
+    for (index, plan) in plans.iter().enumerate() {
+        let mut keep = Vec::new();
+        // An empty synced directory (or a missing source root) in RAM means a
+        // broken or half-populated workspace, never an intent to empty the
+        // project. A subdirectory removed outright is an ordinary deletion.
+        let emptied = !plan.sparse
+            && plan.dest.is_dir()
+            && (plan.source.exists() || is_source_root(cfg, &plan.rel))
+            && is_empty_dir(&plan.source)?
+            && plan.changes.iter().any(|c| c.kind == ChangeKind::Deleted);
+        if emptied {
+            warn!(
+                "{} is empty in RAM but not on disk; not deleting anything there",
+                plan.rel.display()
+            );
+        }
+        for change in &plan.changes {
+            let project_path = join_relative(&plan.rel, &change.path);
+            if change.kind == ChangeKind::Deleted
+                && protect.is_excluded(&project_path, change.file_type == FileType::Dir)
+            {
+                debug!("{} is protected; not deleting", project_path.display());
+                continue;
+            }
+            if change.kind == ChangeKind::Deleted && emptied {
+                continue;
+            }
+            if change.file_type == FileType::Dir {
+                keep.push(change.clone());
+                continue;
+            }
+            let origin = classify(
+                baseline.as_ref(),
+                &project_path,
+                &native::target(&plan.source, &change.path),
+                &native::target(&plan.dest, &change.path),
+            )?;
+            match origin {
+                ChangeOrigin::Ram => keep.push(change.clone()),
+                ChangeOrigin::Disk => {
+                    debug!("leaving disk-side change to {}", project_path.display());
+                    disk_only += 1;
+                }
+                ChangeOrigin::Conflict => conflicts.push((index, change)),
+            }
+        }
+        accepted.push(keep);
+    }
+
+    let conflicts: Vec<(usize, &FileChange, Conflict)> = conflicts
+        .into_iter()
+        .map(|(index, change)| {
+            let plan = &plans[index];
+            let conflict = Conflict {
+                path: join_relative(&plan.rel, &change.path),
+                ram: native::target(&plan.source, &change.path),
+                disk: native::target(&plan.dest, &change.path),
+                workspace: native::target(&plan.workspace, &change.path),
+            };
+            (index, change, conflict)
+        })
+        .collect();
+    let resolutions = if conflicts.is_empty() {
+        vec![]
+    } else {
+        let list: Vec<Conflict> = conflicts.iter().map(|(_, _, c)| c.clone()).collect();
+        resolve(&cfg.raw.sync, &list, opts.noninteractive)?
+    };
+    let mut pulled = Vec::new();
+    let mut kept_both = Vec::new();
+    for ((index, change, conflict), resolution) in conflicts.iter().zip(resolutions) {
+        info!("conflict in {}: {:?}", conflict.path.display(), resolution);
+        match resolution {
+            Resolution::Ours => accepted[*index].push((*change).clone()),
+            Resolution::Theirs => pulled.push(conflict.path.clone()),
+            Resolution::KeepBoth => kept_both.push(conflict),
+        }
+    }
+    let touched: Vec<PathBuf> = plans
+        .iter()
+        .zip(&accepted)
+        .flat_map(|(plan, changes)| {
+            changes
+                .iter()
+                .filter(|c| c.file_type != FileType::Dir)
+                .map(|c| join_relative(&plan.rel, &c.path))
+        })
+        .collect();
+    if cfg.raw.git.require_clean && !opts.ignore_dirty {
+        // A dirty file still matching the baseline was mirrored (or written
+        // by an earlier sync back) as is, so the RAM copy already has it.
+        let mut candidates = BTreeSet::new();
+        for path in &touched {
+            let entry = baseline.as_ref().and_then(|b| b.entries.get(path));
+            if !entry.map_or(Ok(false), |e| e.matches(&cfg.orig_root.join(path)))? {
+                candidates.insert(path.clone());
+            }
+        }
+        git::ensure_clean(cfg, Some(&candidates), "not overwriting them")?;
+    }
+    if let Some(limit) = cfg.raw.sync.max_delete {
+        if !opts.allow_mass_delete {
+            check_delete_limit(limit, baseline.as_ref(), plans, &accepted)?;
+        }
+    }
+
+    let mut run_backup = None;
+    if cfg.raw.backup.enabled {
+        // Conflict copies are written too, so undo can take them back.
+        let mut saved = touched.clone();
+        saved.extend(kept_both.iter().flat_map(|c| keep_both_paths(&c.path)));
+        if let Some(backup) = Backup::create(cfg, &saved)? {
+            info!(
+                "saved previous disk state of {} files as backup {}",
+                backup.entries.len(),
+                backup.id
+            );
+            if opts.session_backup.is_none() {
+                backup::prune(cfg, None)?;
+            }
+            run_backup = Some(backup);
+        }
+    }
+    // Every refusal has had its say; only now does anything reach the disk.
+    for conflict in &kept_both {
+        keep_both(&conflict.ram, &conflict.disk)?;
     }
-    if !noninteractive {
-        info!("synced {} paths back to disk", total_synced);
+
+    let journal = Journal {
+        staging: staging.to_path_buf(),
+        backup: run_backup.as_ref().map(|b| b.id.clone()),
+        plans: plans
+            .iter()
+            .zip(accepted)
+            .map(|(plan, changes)| JournalPlan {
+                rel: plan.rel.clone(),
+                changes,
+            })
+            .collect(),
+    };
+    journal.begin(cfg)?;
+    let written = journal.apply(cfg)?;
+    // In overlay mode the disk copy shows through once the upper entry is
+    // dropped.
+    if !pulled.is_empty() && cfg.raw.workspace.mode != WorkspaceMode::Overlay {
+        let changes: Vec<FileChange> = pulled
+            .iter()
+            .map(|path| FileChange {
+                path: path.clone(),
+                kind: if cfg.orig_root.join(path).exists() {
+                    ChangeKind::Modified
+                } else {
+                    ChangeKind::Deleted
+                },
+                file_type: FileType::File,
+                size_delta: 0,
+            })
+            .collect();
+        let opts = SyncOptions::from_config(&cfg.raw.sync);
+        backend.apply(&cfg.orig_root, &cfg.workspace_root, &changes, &opts)?;
+    }
+    if let Some(baseline) = baseline.as_mut() {
+        baseline.update_from(&cfg.orig_root, &written)?;
+        baseline.update_from(&cfg.orig_root, &pulled)?;
+        baseline.save(cfg)?;
+    }
+    Journal::finish(cfg)?;
+    // Only once the run is complete: until then its own backup is what an
+    // interrupted run rolls back to.
+    if let (Some(backup), Some(session)) = (run_backup, &opts.session_backup) {
+        backup.merge_into_session(cfg, session)?;
     }
-    std::fs::remove_dir_all(&staging).ok();
+    info!(
+        "synced {} files back to disk, kept {} disk-side changes, pulled {} conflicts from disk",
+        written.len(),
+        disk_only,
+        pulled.len()
+    );
+    git::auto_stage(cfg, &written);
     Ok(())
 }
 
-pub fn refresh_from_orig(cfg: &ResolvedConfig, paths: &[PathBuf]) -> Result<()> {
-    let delete = cfg.raw.sync.delete;
+fn is_source_root(cfg: &ResolvedConfig, rel: &Path) -> bool {
+    let rel = join_relative(Path::new(""), rel);
+    cfg.raw
+        .sources
+        .iter()
+        .any(|s| join_relative(Path::new(""), &s.path) == rel)
+}
+
+/// Refuses the sync when the accepted file deletions exceed `limit`, listing
+/// the directories that lose the most files.
+fn check_delete_limit(
+    limit: DeleteLimit,
+    baseline: Option<&Manifest>,
+    plans: &[PathPlan],
+    accepted: &[Vec<FileChange>],
+) -> Result<()> {
+    let deleted: Vec<PathBuf> = plans
+        .iter()
+        .zip(accepted)
+        .flat_map(|(plan, changes)| {
+            changes
+                .iter()
+                .filter(|c| c.kind == ChangeKind::Deleted && c.file_type != FileType::Dir)
+                .map(|c| join_relative(&plan.rel, &c.path))
+        })
+        .collect();
+    if deleted.is_empty() {
+        return Ok(());
+    }
+    // The baseline knows every tracked file even when only a few paths are
+    // being synced; without one, count what is on disk under those paths.
+    let tracked = match baseline {
+        Some(baseline) => baseline.entries.len(),
+        None => {
+            let mut tracked = 0;
+            for plan in plans {
+                tracked += native::list_files(&plan.dest, &plan.opts)?.len();
+            }
+            tracked
+        }
+    };
+    enforce_delete_limit(limit, &deleted, tracked)
+}
+
+/// Fails with a per-directory report when `deleted` (project-relative files)
+/// exceeds what `limit` allows out of `tracked` files.
+pub(crate) fn enforce_delete_limit(
+    limit: DeleteLimit,
+    deleted: &[PathBuf],
+    tracked: usize,
+) -> Result<()> {
+    let allowed = limit.allowed(tracked);
+    if deleted.len() <= allowed {
+        return Ok(());
+    }
+    let mut by_dir: BTreeMap<PathBuf, usize> = BTreeMap::new();
+    for path in deleted {
+        let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        *by_dir.entry(dir).or_default() += 1;
+    }
+    let mut by_dir: Vec<(PathBuf, usize)> = by_dir.into_iter().collect();
+    by_dir.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let report: Vec<String> = by_dir
+        .iter()
+        .take(10)
+        .map(|(dir, count)| {
+            let dir = if dir.as_os_str().is_empty() {
+                ".".to_string()
+            } else {
+                format!("{}/", dir.display())
+            };
+            format!("  {count:>6}  {dir}")
+        })
+        .collect();
+    bail!(
+        "refusing to sync back: {} of {} tracked files would be deleted (sync.max_delete allows {}):\n{}\n\
+         pass --allow-mass-delete to proceed anyway",
+        deleted.len(),
+        tracked,
+        allowed,
+        report.join("\n")
+    )
+}
+
+/// Project-relative files a refresh of `paths` would overwrite even though
+/// their RAM copy has moved away from the baseline.
+pub fn ram_edits(cfg: &ResolvedConfig, paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let backend = backend_for(cfg.raw.sync.backend);
+    let baseline = Manifest::load(cfg)?;
+    let lazy = LazySet::load(cfg)?;
+    let mut edited = Vec::new();
     for rel in paths {
+        let Some(opts) = options_for_path(cfg, rel)? else {
+            continue;
+        };
         let src = cfg.orig_root.join(rel);
         let dest = cfg.workspace_root.join(rel);
-        let opts = SyncOptions {
-            delete,
-            include: vec![],
-            exclude: vec![],
-            itemize: false,
-            dry_run: false,
+        for change in backend.plan(&src, &dest, &opts)? {
+            if change.file_type == FileType::Dir {
+                continue;
+            }
+            let project_path = join_relative(rel, &change.path);
+            if lazy.is_pending(&project_path) {
+                continue;
+            }
+            // Seen from the RAM side: `classify` calls the disk its "disk".
+            let origin = classify(
+                baseline.as_ref(),
+                &project_path,
+                &native::target(&dest, &change.path),
+                &native::target(&src, &change.path),
+            )?;
+            if origin != ChangeOrigin::Disk {
+                edited.push(project_path);
+            }
+        }
+    }
+    Ok(edited)
+}
+
+pub fn refresh_from_orig(cfg: &ResolvedConfig, paths: &[PathBuf]) -> Result<()> {
+    refresh_keeping(cfg, paths, &BTreeSet::new())
+}
+
+/// Refreshes `paths` from disk, leaving the project-relative files in `keep`
+/// untouched in RAM.
+pub fn refresh_keeping(
+    cfg: &ResolvedConfig,
+    paths: &[PathBuf],
+    keep: &BTreeSet<PathBuf>,
+) -> Result<()> {
+    if cfg.raw.workspace.mode == WorkspaceMode::Overlay {
+        info!("overlay mode: disk changes already show through the mount");
+        return Ok(());
+    }
+    let backend = backend_for(cfg.raw.sync.backend);
+    let mut baseline = Manifest::load(cfg)?;
+    let lazy = LazySet::load(cfg)?;
+    for rel in paths {
+        let Some(opts) = options_for_path(cfg, rel)? else {
+            warn!("{} is excluded by its source; skipping", rel.display());
+            continue;
         };
-        sync_path(&src, &dest, SyncDirection::OrigToWorkspace, opts)?;
+        let src = cfg.orig_root.join(rel);
+        let dest = cfg.workspace_root.join(rel);
+        let changes: Vec<FileChange> = backend
+            .plan(&src, &dest, &opts)?
+            .into_iter()
+            .filter(|c| !keep.contains(&join_relative(rel, &c.path)))
+            .filter(|c| {
+                c.kind != ChangeKind::Added || !lazy.is_pending(&join_relative(rel, &c.path))
+            })
+            .collect();
+        backend.apply(&src, &dest, &changes, &opts)?;
+        if let Some(baseline) = baseline.as_mut() {
+            let touched: Vec<PathBuf> = changes
+                .iter()
+                .filter(|c| c.file_type != FileType::Dir)
+                .map(|c| join_relative(rel, &c.path))
+                .collect();
+            baseline.update_from(&cfg.workspace_root, &touched)?;
+        }
+    }
+    if let Some(baseline) = baseline {
+        baseline.save(cfg)?;
     }
     Ok(())
 }
@@ -4,7 +4,7 @@ use nix::sys::statfs::{statfs, Statfs};
 use sha1::Digest;
 use std::env;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 #[derive(Debug, Clone)]
 pub struct FsStatus {
@@ -12,6 +12,8 @@ pub struct FsStatus {
     pub total: u64,
     pub available: u64,
     pub used: u64,
+    pub inodes: u64,
+    pub inodes_free: u64,
 }
 
 pub fn find_project_root(start: &Path) -> Result<PathBuf> {
@@ -50,6 +52,13 @@ pub fn expand_placeholders(template: &str, project_slug: &str) -> String {
     value
 }
 
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
 pub fn ensure_dir(path: &Path) -> Result<()> {
     fs::create_dir_all(path)
         .with_context(|| format!("failed to create directory {}", path.display()))?;
@@ -75,15 +84,73 @@ pub fn fs_status(path: &Path) -> Result<FsStatus> {
         total,
         available,
         used,
+        inodes: stat.files() as u64,
+        inodes_free: stat.files_free() as u64,
     })
 }
 
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file =
+        fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut hasher = sha1::Sha1::new();
+    std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Whether `path` is missing or an empty directory.
+pub fn is_empty_dir(path: &Path) -> Result<bool> {
+    if !path.exists() {
+        return Ok(true);
+    }
+    if !path.is_dir() {
+        return Ok(false);
+    }
+    let mut entries =
+        fs::read_dir(path).with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(entries.next().is_none())
+}
+
+/// Total size of the regular files below `path`.
+pub fn dir_size(path: &Path) -> u64 {
+    walkdir::WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum()
+}
+
 pub fn is_tmpfs(path: &Path) -> Result<bool> {
     let stat: Statfs =
         statfs(path).with_context(|| format!("statfs failed for {}", path.display()))?;
     Ok(stat.filesystem_type().0 as i64 == libc::TMPFS_MAGIC)
 }
 
+/// Parses a byte count such as `1048576`, `512M`, `8G` or `1.5GiB`. Suffixes
+/// are binary (`K` = 1024).
+pub fn parse_size(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("invalid size {text:?}"))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let shift = match unit.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => anyhow::bail!("invalid size unit in {text:?}"),
+    };
+    Ok((number * (1u64 << shift) as f64) as u64)
+}
+
 pub fn format_bytes(bytes: u64) -> String {
     const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
     let mut value = bytes as f64;
@@ -95,6 +162,49 @@ pub fn format_bytes(bytes: u64) -> String {
     format!("{value:.2} {}", UNITS[unit])
 }
 
+/// Formats seconds since the epoch as a compact UTC timestamp,
+/// `YYYYMMDDTHHMMSSZ`, which sorts chronologically.
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil-from-days (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// A timestamp id for a new entry under `dir`, suffixed when several are
+/// created within the same second.
+pub fn timestamp_id(dir: &Path, secs: u64) -> String {
+    let stamp = format_timestamp(secs);
+    let mut id = stamp.clone();
+    let mut n = 1;
+    while dir.join(&id).exists() {
+        id = format!("{stamp}-{n}");
+        n += 1;
+    }
+    id
+}
+
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
 pub fn prompt_confirm(message: &str, default: bool) -> Result<bool> {
     use dialoguer::Confirm;
     let response = Confirm::new()
@@ -104,6 +214,16 @@ pub fn prompt_confirm(message: &str, default: bool) -> Result<bool> {
     Ok(response)
 }
 
+pub fn prompt_select(message: &str, items: &[&str]) -> Result<usize> {
+    use dialoguer::Select;
+    let choice = Select::new()
+        .with_prompt(message)
+        .items(items)
+        .default(0)
+        .interact()?;
+    Ok(choice)
+}
+
 pub fn path_with_trailing_slash(path: &Path) -> String {
     let mut s = path.to_string_lossy().into_owned();
     if !s.ends_with('/') {
@@ -112,6 +232,41 @@ pub fn path_with_trailing_slash(path: &Path) -> String {
     s
 }
 
+/// Joins `rel` onto `base`, dropping `.` components so `./src` reads as `src`.
+pub fn join_relative(base: &Path, rel: &Path) -> PathBuf {
+    base.components()
+        .chain(rel.components())
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
+}
+
+/// Turns a path given on the command line (absolute, or relative to `cwd`)
+/// into one relative to the project `root`. Paths need not exist, so `..` is
+/// resolved lexically once `cwd` is canonical.
+pub fn project_relative(root: &Path, cwd: &Path, path: &Path) -> Result<PathBuf> {
+    let cwd = cwd
+        .canonicalize()
+        .with_context(|| format!("failed to resolve {}", cwd.display()))?;
+    let mut absolute = PathBuf::new();
+    for part in cwd.join(path).components() {
+        match part {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                absolute.pop();
+            }
+            other => absolute.push(other),
+        }
+    }
+    match absolute.strip_prefix(root) {
+        Ok(rel) => Ok(rel.to_path_buf()),
+        Err(_) => anyhow::bail!(
+            "{} is outside the project root {}",
+            path.display(),
+            root.display()
+        ),
+    }
+}
+
 pub fn ensure_within_root(root: &Path, candidate: &Path) -> Result<()> {
     let root = root.canonicalize()?;
     let candidate = candidate.canonicalize()?;
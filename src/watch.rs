@@ -0,0 +1,396 @@
+use crate::backup;
+use crate::config::{ResolvedConfig, WorkspaceMode};
+use crate::manifest::Manifest;
+use crate::native::Filter;
+use crate::syncer::{ram_edits, refresh_from_orig, sync_back, SyncBackOptions};
+use crate::util::join_relative;
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+use walkdir::WalkDir;
+
+/// Longest a watcher sleeps before re-checking its stop flag.
+const TICK: Duration = Duration::from_millis(200);
+
+/// How long a failed flush waits before it is retried without new edits.
+const RETRY: Duration = Duration::from_secs(5);
+
+/// Decides which project-relative paths a watcher cares about: anything inside
+/// a source and not excluded by that source's filters, minus build dirs.
+struct Scope {
+    sources: Vec<(PathBuf, Filter)>,
+    build_dirs: Vec<PathBuf>,
+}
+
+impl Scope {
+    fn new(cfg: &ResolvedConfig) -> Result<Self> {
+        let mut sources = Vec::new();
+        for source in &cfg.raw.sources {
+            let root = join_relative(Path::new(""), &source.path);
+            sources.push((root, Filter::new(&source.include, &source.exclude)?));
+        }
+        // Longest root first, so the owning source is found first.
+        sources.sort_by_key(|(root, _)| std::cmp::Reverse(root.components().count()));
+        let build_dirs = cfg
+            .raw
+            .build_dirs
+            .iter()
+            .map(|b| join_relative(Path::new(""), &b.path))
+            .collect();
+        Ok(Scope {
+            sources,
+            build_dirs,
+        })
+    }
+
+    fn contains(&self, rel: &Path, is_dir: bool) -> bool {
+        if self.build_dirs.iter().any(|b| rel.starts_with(b)) {
+            return false;
+        }
+        let Some((root, filter)) = self.sources.iter().find(|(root, _)| rel.starts_with(root))
+        else {
+            // Ancestors of a source root must be watched to reach it.
+            return self.sources.iter().any(|(root, _)| root.starts_with(rel));
+        };
+        let sub = rel.strip_prefix(root).unwrap_or(rel);
+        let mut prefix = PathBuf::new();
+        for part in sub.components() {
+            prefix.push(part);
+            if filter.is_excluded(&prefix, prefix != sub || is_dir) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Whether `rel` is synced itself rather than only leading to a source.
+    fn syncs(&self, rel: &Path) -> bool {
+        self.sources.iter().any(|(root, _)| rel.starts_with(root))
+    }
+}
+
+/// Inotify watches on every in-scope directory below `root`.
+struct TreeWatch {
+    inotify: Inotify,
+    root: PathBuf,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+}
+
+fn watch_flags() -> AddWatchFlags {
+    AddWatchFlags::IN_CLOSE_WRITE
+        | AddWatchFlags::IN_MODIFY
+        | AddWatchFlags::IN_ATTRIB
+        | AddWatchFlags::IN_CREATE
+        | AddWatchFlags::IN_DELETE
+        | AddWatchFlags::IN_MOVED_FROM
+        | AddWatchFlags::IN_MOVED_TO
+        | AddWatchFlags::IN_DONT_FOLLOW
+        | AddWatchFlags::IN_ONLYDIR
+}
+
+impl TreeWatch {
+    fn new(root: &Path, scope: &Scope) -> Result<Self> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+            .context("failed to initialise inotify")?;
+        let mut watch = TreeWatch {
+            inotify,
+            root: root.to_path_buf(),
+            dirs: HashMap::new(),
+        };
+        watch.add_tree(Path::new(""), scope)?;
+        Ok(watch)
+    }
+
+    fn add_tree(&mut self, rel: &Path, scope: &Scope) -> Result<()> {
+        let base = self.root.join(rel);
+        let walker = WalkDir::new(&base)
+            .follow_links(false)
+            .into_iter()
+            .filter_entry(|e| {
+                let sub = e.path().strip_prefix(&self.root).unwrap_or(e.path());
+                e.file_type().is_dir() && (sub.as_os_str().is_empty() || scope.contains(sub, true))
+            });
+        for item in walker {
+            let Ok(item) = item else { continue };
+            let sub = item.path().strip_prefix(&self.root)?.to_path_buf();
+            match self.inotify.add_watch(item.path(), watch_flags()) {
+                Ok(wd) => {
+                    self.dirs.insert(wd, sub);
+                }
+                Err(e) => debug!("not watching {}: {e}", item.path().display()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Blocks up to `timeout` for events; returns false if none arrived.
+    fn wait(&self, timeout: Duration) -> Result<bool> {
+        let mut fds = [PollFd::new(&self.inotify, PollFlags::POLLIN)];
+        match poll(&mut fds, timeout.as_millis() as i32) {
+            Ok(n) => Ok(n > 0),
+            Err(Errno::EINTR) => Ok(false),
+            Err(e) => Err(e).context("failed to poll inotify"),
+        }
+    }
+
+    /// Collects changed in-scope paths into `out`. Returns true when the
+    /// kernel queue overflowed and events were lost.
+    fn read(&mut self, scope: &Scope, out: &mut BTreeSet<PathBuf>) -> Result<bool> {
+        let events = match self.inotify.read_events() {
+            Ok(events) => events,
+            Err(Errno::EAGAIN) => return Ok(false),
+            Err(e) => return Err(e).context("failed to read inotify events"),
+        };
+        let mut overflow = false;
+        for event in events {
+            if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                overflow = true;
+                continue;
+            }
+            if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+                self.dirs.remove(&event.wd);
+                continue;
+            }
+            let (Some(dir), Some(name)) = (self.dirs.get(&event.wd), &event.name) else {
+                continue;
+            };
+            let rel = dir.join(name);
+            let is_dir = event.mask.contains(AddWatchFlags::IN_ISDIR);
+            if !scope.contains(&rel, is_dir) {
+                continue;
+            }
+            if is_dir
+                && event
+                    .mask
+                    .intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO)
+            {
+                self.add_tree(&rel, scope)?;
+            }
+            if scope.syncs(&rel) {
+                out.insert(rel);
+            }
+        }
+        Ok(overflow)
+    }
+}
+
+/// Drops paths that sit below another path in the set.
+fn outermost(paths: &BTreeSet<PathBuf>) -> Vec<PathBuf> {
+    let mut result: Vec<PathBuf> = Vec::new();
+    for path in paths {
+        if !result.iter().any(|p| path.starts_with(p)) {
+            result.push(path.clone());
+        }
+    }
+    result
+}
+
+fn flush_to_disk(cfg: &ResolvedConfig, paths: &[PathBuf], session: &str) -> Result<()> {
+    let opts = SyncBackOptions {
+        noninteractive: true,
+        session_backup: Some(session.to_string()),
+        ..SyncBackOptions::default()
+    };
+    sync_back(cfg, paths, &opts)?;
+    info!("flushed {} changed paths to disk", paths.len());
+    Ok(())
+}
+
+/// Which side a watcher observes; changes flow to the other one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Watch `workspace_root` and sync back to disk.
+    ToDisk,
+    /// Watch `orig_root` and refresh the RAM workspace.
+    FromDisk,
+}
+
+/// Pulls disk-side edits into RAM. Refuses (and so ends the watch) when any of
+/// the affected files was also edited in RAM since the baseline.
+fn flush_from_disk(cfg: &ResolvedConfig, paths: &[PathBuf]) -> Result<()> {
+    let edited = ram_edits(cfg, paths)?;
+    if !edited.is_empty() {
+        let list: Vec<String> = edited
+            .iter()
+            .map(|p| format!("  {}", p.display()))
+            .collect();
+        bail!(
+            "stopping: {} file(s) changed on disk were also edited in RAM:\n{}\n\
+             sync them back or refresh with ramws sync --from before watching again",
+            edited.len(),
+            list.join("\n")
+        );
+    }
+    refresh_from_orig(cfg, paths)?;
+    info!("pulled {} changed paths into the workspace", paths.len());
+    Ok(())
+}
+
+fn flush(
+    cfg: &ResolvedConfig,
+    direction: Direction,
+    paths: &[PathBuf],
+    session: &str,
+) -> Result<()> {
+    match direction {
+        Direction::ToDisk => flush_to_disk(cfg, paths, session),
+        Direction::FromDisk => flush_from_disk(cfg, paths),
+    }
+}
+
+/// Fails for an overlay workspace. Syncing it back has to unmount the
+/// overlay, which whoever is editing it keeps busy, and a remount would leave
+/// the watches on the old tree; disk changes show through it already.
+pub fn ensure_watchable(cfg: &ResolvedConfig) -> Result<()> {
+    if cfg.raw.workspace.mode == WorkspaceMode::Overlay {
+        bail!(
+            "ramws watch and sync.continuous do not work with workspace.mode: overlay; \
+             run ramws sync --back from outside the workspace instead"
+        );
+    }
+    Ok(())
+}
+
+/// Watches one side of the workspace and mirrors source changes to the other
+/// once edits have been quiet for `sync.debounce_ms`. Returns after `stop` is
+/// set, flushing anything still pending.
+pub fn watch(cfg: &ResolvedConfig, direction: Direction, stop: &AtomicBool) -> Result<()> {
+    ensure_watchable(cfg)?;
+    if !cfg.workspace_root.exists() {
+        bail!(
+            "workspace not found at {}; run ramws start",
+            cfg.workspace_root.display()
+        );
+    }
+    if direction == Direction::FromDisk && Manifest::load(cfg)?.is_none() {
+        bail!("no baseline recorded; run ramws start before watching the disk");
+    }
+    let root = match direction {
+        Direction::ToDisk => &cfg.workspace_root,
+        Direction::FromDisk => &cfg.orig_root,
+    };
+    let scope = Scope::new(cfg)?;
+    let mut watch = TreeWatch::new(root, &scope)?;
+    info!(
+        "watching {} ({} directories)",
+        root.display(),
+        watch.dirs.len()
+    );
+    // Every flush of this session backs up into one backup, so undo can
+    // return to the disk state from before the watch began.
+    let session = backup::session_id(cfg);
+    let debounce = Duration::from_millis(cfg.raw.sync.debounce_ms);
+    let mut pending = BTreeSet::new();
+    let mut overflow = false;
+    let mut last_event = Instant::now();
+    // A sync back that failed (a conflict, `require_clean`, I/O) keeps its
+    // paths pending; they are retried on the next edit or after `RETRY`.
+    let mut failed: Option<(Instant, String)> = None;
+    loop {
+        let stopping = stop.load(Ordering::SeqCst);
+        if !stopping && watch.wait(debounce.min(TICK))? {
+            overflow |= watch.read(&scope, &mut pending)?;
+            last_event = Instant::now();
+            continue;
+        }
+        if stopping {
+            overflow |= watch.read(&scope, &mut pending)?;
+        }
+        let quiet = stopping || last_event.elapsed() >= debounce;
+        let due = failed
+            .as_ref()
+            .is_none_or(|(at, _)| last_event > *at || at.elapsed() >= RETRY);
+        if quiet && due && (overflow || !pending.is_empty()) {
+            let paths = if overflow {
+                warn!("inotify queue overflowed; syncing all sources");
+                cfg.raw.sources.iter().map(|s| s.path.clone()).collect()
+            } else {
+                outermost(&pending)
+            };
+            match flush(cfg, direction, &paths, &session) {
+                Ok(()) => {
+                    pending.clear();
+                    overflow = false;
+                    if failed.take().is_some() {
+                        eprintln!("ramws watch: synced {} paths back to disk", paths.len());
+                    }
+                }
+                Err(e) if direction == Direction::ToDisk && !stopping => {
+                    let message = format!("{e:#}");
+                    // Reported once per distinct failure, not on every retry.
+                    if failed.as_ref().is_none_or(|(_, last)| *last != message) {
+                        eprintln!(
+                            "Warning: edits to {} paths are not on disk yet; retrying: {message}",
+                            paths.len()
+                        );
+                    }
+                    failed = Some((Instant::now(), message));
+                }
+                Err(e) if direction == Direction::ToDisk => {
+                    return Err(e).with_context(|| {
+                        format!("edits to {} paths were not synced back", paths.len())
+                    })
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if stopping {
+            return Ok(());
+        }
+    }
+}
+
+/// A RAM-to-disk watcher running on its own thread, as used by
+/// `sync.continuous`.
+pub struct BackgroundWatch {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<Result<()>>,
+}
+
+impl BackgroundWatch {
+    pub fn spawn(cfg: ResolvedConfig) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&stop);
+        let handle = thread::spawn(move || watch(&cfg, Direction::ToDisk, &flag));
+        BackgroundWatch { stop, handle }
+    }
+
+    /// Flushes pending edits and stops the watcher.
+    pub fn stop(self) -> Result<()> {
+        self.stop.store(true, Ordering::SeqCst);
+        match self.handle.join() {
+            Ok(result) => result,
+            Err(_) => bail!("watcher thread panicked"),
+        }
+    }
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: nix::libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Turns SIGINT/SIGTERM into a stop flag so a foreground watcher can flush
+/// before exiting.
+pub fn stop_on_signals() -> Result<&'static AtomicBool> {
+    let action = SigAction::new(
+        SigHandler::Handler(on_signal),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    for signal in [Signal::SIGINT, Signal::SIGTERM] {
+        // SAFETY: the handler only stores to an atomic.
+        unsafe { sigaction(signal, &action) }.context("failed to install signal handler")?;
+    }
+    Ok(&INTERRUPTED)
+}
@@ -1,11 +1,44 @@
-use crate::config::{BuildDirType, ResolvedConfig};
-use crate::syncer::{sync_path, SyncDirection, SyncOptions};
-use crate::util::{ensure_dir, is_tmpfs};
-use anyhow::{Context, Result};
+use crate::budget;
+use crate::checkpoint::Checkpoint;
+use crate::config::{BuildDirSpec, BuildDirType, GitMode, ResolvedConfig, WorkspaceMode};
+use crate::git;
+use crate::lazy::{self, LazySet};
+use crate::manifest::Manifest;
+use crate::mount;
+use crate::overlay;
+use crate::syncer::{
+    backend_for, ram_edits, refresh_keeping, source_options, sync_path, SyncOptions,
+};
+use crate::util::{dir_size, ensure_dir, is_empty_dir, is_tmpfs};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::collections::BTreeSet;
 use std::fs;
+use std::os::unix::fs::symlink;
 use std::path::PathBuf;
 use tracing::{info, warn};
 
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStatus {
+    pub path: String,
+    pub store: String,
+    pub ram_bytes: u64,
+    pub store_bytes: u64,
+}
+
+/// How `ramws start` should populate the workspace.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StartOptions {
+    pub refresh_sources_only: bool,
+    pub lazy: bool,
+    /// Mirror the project even when `git.require_clean` finds uncommitted
+    /// changes.
+    pub ignore_dirty: bool,
+    /// Rebuild the workspace from the latest checkpoint instead of the
+    /// project on disk.
+    pub from_checkpoint: bool,
+}
+
 pub struct Workspace {
     pub config: ResolvedConfig,
 }
@@ -15,7 +48,107 @@ impl Workspace {
         Self { config }
     }
 
-    pub fn ensure(&self, refresh_sources_only: bool) -> Result<()> {
+    /// Creates or refreshes the workspace. With `lazy`, a new workspace gets
+    /// only its directory skeleton and hot files; see `lazy::populate`. With
+    /// `from_checkpoint` it gets the latest checkpoint's contents, and the
+    /// baseline recorded with it so unsynced edits still show up as RAM
+    /// changes.
+    pub fn ensure(&self, opts: &StartOptions) -> Result<()> {
+        let StartOptions {
+            refresh_sources_only,
+            lazy,
+            ignore_dirty,
+            from_checkpoint,
+        } = *opts;
+        let worktree = self.config.raw.git.mode == GitMode::Worktree;
+        if self.config.raw.workspace.mode == WorkspaceMode::Overlay {
+            if worktree {
+                bail!("git.mode: worktree does not work with workspace.mode: overlay");
+            }
+            if !ignore_dirty && !mount::is_mount_point(&self.config.workspace_root) {
+                git::ensure_clean(&self.config, None, "not mounting the project")?;
+            }
+            return self.ensure_overlay();
+        }
+        let checkpoint = if from_checkpoint {
+            match Checkpoint::latest(&self.config)? {
+                Some(checkpoint) => Some(checkpoint),
+                None => bail!(
+                    "no checkpoint found in {}",
+                    self.config.checkpoint_dir().display()
+                ),
+            }
+        } else {
+            None
+        };
+        // An empty root is a workspace whose contents went away with its
+        // tmpfs (reboot, or the end of a namespaced shell).
+        let resume = checkpoint.is_none()
+            && self.exists()
+            && !is_empty_dir(&self.config.workspace_root)?
+            && Manifest::location(&self.config).exists();
+        if !resume && checkpoint.is_none() && !ignore_dirty {
+            git::ensure_clean(&self.config, None, "not mirroring the project")?;
+        }
+        self.prepare_dirs(refresh_sources_only)?;
+        if let Some(checkpoint) = &checkpoint {
+            budget::check(&self.config, &budget::estimate_checkpoint(checkpoint))?;
+            // Before the caches are seeded, which only fills empty dirs.
+            checkpoint.restore(&self.config)?;
+        } else if !resume && !lazy {
+            budget::check(&self.config, &budget::estimate(&self.config)?)?;
+        }
+        if !refresh_sources_only {
+            self.link_build_dirs()?;
+            self.seed_caches(false)?;
+        }
+        if checkpoint.is_some() {
+            LazySet::default().save(&self.config)?;
+            if worktree {
+                git::reset_worktree_index(&self.config)?;
+            }
+            return Ok(());
+        }
+        if resume {
+            if lazy {
+                warn!("workspace is already populated; ignoring --lazy");
+            }
+            return self.refresh_sources();
+        }
+        if lazy {
+            lazy::populate(&self.config)?.save(&self.config)?;
+        } else {
+            LazySet::default().save(&self.config)?;
+            // populate sources through the configured sync backend
+            let backend = backend_for(self.config.raw.sync.backend);
+            for source in &self.config.raw.sources {
+                let src_path = self.config.orig_root.join(&source.path);
+                let dest_path = self.config.workspace_root.join(&source.path);
+                ensure_dir(&dest_path)?;
+                let opts = source_options(&self.config, source);
+                sync_path(backend.as_ref(), &src_path, &dest_path, &opts)?;
+            }
+        }
+        if worktree {
+            git::reset_worktree_index(&self.config)?;
+        }
+        Manifest::record(&self.config)?.save(&self.config)?;
+        Ok(())
+    }
+
+    /// In overlay mode the project shows through the mount, so nothing is
+    /// copied: mount it and create the build dirs in the upper layer.
+    fn ensure_overlay(&self) -> Result<()> {
+        mount::mount_workspace(&self.config, false)?;
+        overlay::record_baseline(&self.config)?;
+        for build in &self.config.raw.build_dirs {
+            ensure_dir(&self.config.workspace_root.join(&build.path))?;
+        }
+        Ok(())
+    }
+
+    fn prepare_dirs(&self, refresh_sources_only: bool) -> Result<()> {
+        mount::mount_workspace(&self.config, false)?;
         ensure_dir(&self.config.workspace_root)?;
         if !is_tmpfs(&self.config.workspace_root)? {
             warn!(
@@ -23,34 +156,186 @@ impl Workspace {
                 self.config.workspace_root.display()
             );
         }
+        // Before the build dirs: git only adds a worktree in an empty dir.
+        if self.config.raw.git.mode == GitMode::Worktree {
+            git::add_worktree(&self.config)?;
+        }
         if !refresh_sources_only {
             for build in &self.config.raw.build_dirs {
                 let path = self.config.workspace_root.join(&build.path);
                 ensure_dir(&path)?;
             }
         }
-        // populate sources via rsync
-        for source in &self.config.raw.sources {
-            let src_path = self.config.orig_root.join(&source.path);
-            let dest_path = self.config.workspace_root.join(&source.path);
-            ensure_dir(&dest_path)?;
-            let opts = SyncOptions {
-                delete: self.config.raw.sync.delete,
-                include: source.include.clone(),
-                exclude: source.exclude.clone(),
-                itemize: false,
-                dry_run: false,
-            };
-            sync_path(&src_path, &dest_path, SyncDirection::OrigToWorkspace, opts)?;
+        Ok(())
+    }
+
+    /// Points each `link: true` build dir in the project at its RAM copy,
+    /// moving whatever was there into the state dir first.
+    fn link_build_dirs(&self) -> Result<()> {
+        for build in self.config.raw.build_dirs.iter().filter(|b| b.link) {
+            let disk = self.config.orig_root.join(&build.path);
+            let ram = self.config.workspace_root.join(&build.path);
+            let aside = self.config.linked_aside(build);
+            match fs::symlink_metadata(&disk) {
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    if fs::read_link(&disk)? != ram {
+                        warn!(
+                            "{} is a symlink ramws did not create; not linking it",
+                            disk.display()
+                        );
+                    }
+                    continue;
+                }
+                Ok(_) => {
+                    if fs::symlink_metadata(&aside).is_ok() {
+                        bail!(
+                            "cannot link {}: {} still holds a previous copy",
+                            disk.display(),
+                            aside.display()
+                        );
+                    }
+                    if let Some(parent) = aside.parent() {
+                        ensure_dir(parent)?;
+                    }
+                    fs::rename(&disk, &aside)
+                        .with_context(|| format!("failed to move {} aside", disk.display()))?;
+                }
+                Err(_) => {
+                    if let Some(parent) = disk.parent() {
+                        ensure_dir(parent)?;
+                    }
+                }
+            }
+            symlink(&ram, &disk).with_context(|| format!("failed to link {}", disk.display()))?;
+            info!("linked {} to {}", disk.display(), ram.display());
         }
         Ok(())
     }
 
+    /// Removes the symlinks made by `link_build_dirs` and puts the project's
+    /// own copies back. Anything at the disk path that is not our symlink is
+    /// left alone.
+    fn unlink_build_dirs(&self) -> Result<()> {
+        for build in self.config.raw.build_dirs.iter().filter(|b| b.link) {
+            let disk = self.config.orig_root.join(&build.path);
+            let ram = self.config.workspace_root.join(&build.path);
+            let aside = self.config.linked_aside(build);
+            let ours = fs::symlink_metadata(&disk).is_ok_and(|m| m.file_type().is_symlink())
+                && fs::read_link(&disk)? == ram;
+            if ours {
+                fs::remove_file(&disk)
+                    .with_context(|| format!("failed to remove link {}", disk.display()))?;
+            } else if fs::symlink_metadata(&disk).is_ok() {
+                if aside.exists() {
+                    warn!(
+                        "{} was replaced while linked; its previous copy stays in {}",
+                        disk.display(),
+                        aside.display()
+                    );
+                }
+                continue;
+            }
+            if fs::symlink_metadata(&aside).is_ok() {
+                fs::rename(&aside, &disk)
+                    .with_context(|| format!("failed to restore {}", disk.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Cache build dirs paired with their RAM path and persistent store.
+    fn caches(&self) -> Vec<(&BuildDirSpec, PathBuf, PathBuf)> {
+        self.config
+            .raw
+            .build_dirs
+            .iter()
+            .filter_map(|b| {
+                let store = self.config.cache_store(b)?;
+                Some((b, self.config.workspace_root.join(&b.path), store))
+            })
+            .collect()
+    }
+
+    fn cache_options(&self) -> SyncOptions {
+        SyncOptions::from_config(&self.config.raw.sync)
+    }
+
+    /// Copies cache build dirs from their persistent store into RAM. Unless
+    /// `force` is set, only empty RAM dirs are seeded, so a running workspace
+    /// keeps its newer contents.
+    pub fn seed_caches(&self, force: bool) -> Result<()> {
+        let backend = backend_for(self.config.raw.sync.backend);
+        for (build, ram, store) in self.caches() {
+            if !store.is_dir() || (!force && !is_empty_dir(&ram)?) {
+                continue;
+            }
+            info!(
+                "seeding cache {} from {}",
+                build.path.display(),
+                store.display()
+            );
+            sync_path(backend.as_ref(), &store, &ram, &self.cache_options())?;
+        }
+        Ok(())
+    }
+
+    /// Writes cache build dirs back to their persistent store.
+    pub fn save_caches(&self) -> Result<()> {
+        let backend = backend_for(self.config.raw.sync.backend);
+        for (build, ram, store) in self.caches() {
+            if !ram.is_dir() {
+                continue;
+            }
+            info!(
+                "saving cache {} to {}",
+                build.path.display(),
+                store.display()
+            );
+            ensure_dir(&store)?;
+            sync_path(backend.as_ref(), &ram, &store, &self.cache_options())?;
+        }
+        Ok(())
+    }
+
+    /// Size of each cache build dir in RAM and in its store.
+    pub fn cache_sizes(&self) -> Vec<CacheStatus> {
+        self.caches()
+            .into_iter()
+            .map(|(build, ram, store)| CacheStatus {
+                path: build.path.display().to_string(),
+                store: store.display().to_string(),
+                ram_bytes: dir_size(&ram),
+                store_bytes: dir_size(&store),
+            })
+            .collect()
+    }
+
+    /// Brings an existing workspace up to date with disk-side changes while
+    /// leaving files edited in RAM (or restored from a checkpoint) alone.
+    fn refresh_sources(&self) -> Result<()> {
+        let paths: Vec<PathBuf> = self
+            .config
+            .raw
+            .sources
+            .iter()
+            .map(|s| s.path.clone())
+            .collect();
+        let edited: BTreeSet<PathBuf> = ram_edits(&self.config, &paths)?.into_iter().collect();
+        if !edited.is_empty() {
+            info!("keeping {} files edited in RAM", edited.len());
+        }
+        refresh_keeping(&self.config, &paths, &edited)
+    }
+
     pub fn exists(&self) -> bool {
         self.config.workspace_root.exists()
     }
 
     pub fn delete(&self) -> Result<()> {
+        // Checked before unmounting, which may take the worktree with it.
+        let linked = self.config.workspace_root.join(".git").is_file();
+        self.unlink_build_dirs()?;
+        mount::unmount_workspace(&self.config)?;
         if self.exists() {
             info!(
                 "removing workspace {}",
@@ -63,6 +348,35 @@ impl Workspace {
                 )
             })?;
         }
+        if linked {
+            if let Err(e) = git::remove_worktree(&self.config) {
+                warn!("failed to unregister the workspace worktree: {e:#}");
+            }
+        }
+        let overlay = self.config.overlay_dir();
+        if overlay.exists() {
+            fs::remove_dir_all(&overlay)
+                .with_context(|| format!("failed to remove {}", overlay.display()))?;
+        }
+        // Scratch dirs moved out of RAM under memory pressure. `spill_dir` is
+        // the user's to choose and may hold other things, so only the dirs
+        // ramws put there go.
+        for build in &self.build_paths_by_role(BuildDirType::Scratch) {
+            let spilled = self.config.spill_dir().join(build);
+            if spilled == self.config.orig_root.join(build) {
+                continue;
+            }
+            if fs::symlink_metadata(&spilled).is_ok_and(|m| m.is_dir()) {
+                fs::remove_dir_all(&spilled)
+                    .with_context(|| format!("failed to remove {}", spilled.display()))?;
+            }
+        }
+        LazySet::default().save(&self.config)?;
+        let baseline = Manifest::location(&self.config);
+        if baseline.exists() {
+            fs::remove_file(&baseline)
+                .with_context(|| format!("failed to remove {}", baseline.display()))?;
+        }
         Ok(())
     }
 
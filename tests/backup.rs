@@ -0,0 +1,87 @@
+use std::fs;
+
+mod common;
+use common::Fixture;
+
+#[test]
+fn undo_restores_disk_state_before_sync() {
+    let fx = Fixture::new("");
+    fx.write_disk("src/edit.rs", "original");
+    fx.write_disk("src/gone.rs", "gone");
+    fx.ramws().arg("start").assert().success();
+    fs::write(fx.ram("src/edit.rs"), "edited").unwrap();
+    fs::remove_file(fx.ram("src/gone.rs")).unwrap();
+    fs::write(fx.ram("src/new.rs"), "new").unwrap();
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("src/edit.rs"), "edited");
+    assert!(!fx.disk("src/gone.rs").exists());
+
+    fx.ramws()
+        .args(["undo", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("src/edit.rs"), "original");
+    assert_eq!(fx.read_disk("src/gone.rs"), "gone");
+    assert!(!fx.disk("src/new.rs").exists());
+}
+
+#[test]
+fn backups_are_listed_and_pruned() {
+    let fx = Fixture::new("backup:\n  keep: 2\n");
+    fx.write_disk("a.txt", "0");
+    fx.ramws().arg("start").assert().success();
+    for round in 1..=3 {
+        fs::write(fx.ram("a.txt"), round.to_string()).unwrap();
+        fx.ramws()
+            .args(["sync", "--back", "--noninteractive"])
+            .assert()
+            .success();
+    }
+    let list = fx
+        .ramws()
+        .args(["--json", "backups", "list"])
+        .output()
+        .unwrap();
+    let backups: serde_json::Value = serde_json::from_slice(&list.stdout).unwrap();
+    let backups = backups.as_array().unwrap();
+    assert_eq!(backups.len(), 2);
+
+    // Undo of a chosen backup restores what that sync overwrote.
+    let first = backups[0]["id"].as_str().unwrap();
+    fx.ramws()
+        .args(["undo", first, "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("a.txt"), "1");
+
+    fx.ramws()
+        .args(["backups", "prune", "--keep", "0"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("removed 2 backups"));
+}
+
+#[test]
+fn undo_covers_keep_both_copies_even_with_keep_zero() {
+    let fx = Fixture::new("sync:\n  conflict: keep-both\nbackup:\n  keep: 0\n");
+    fx.write_disk("a.txt", "base");
+    fx.ramws().arg("start").assert().success();
+    fs::write(fx.ram("a.txt"), "ram").unwrap();
+    fx.write_disk("a.txt", "disk");
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("a.txt.ramws-ours"), "ram");
+
+    fx.ramws()
+        .args(["undo", "--noninteractive"])
+        .assert()
+        .success();
+    assert!(!fx.disk("a.txt.ramws-ours").exists());
+    assert!(!fx.disk("a.txt.ramws-theirs").exists());
+    assert_eq!(fx.read_disk("a.txt"), "disk");
+}
@@ -2,6 +2,9 @@ use predicates::prelude::*;
 use std::fs;
 use tempfile::tempdir;
 
+mod common;
+use common::Fixture;
+
 #[test]
 fn init_creates_config() {
     let dir = tempdir().unwrap();
@@ -29,3 +32,154 @@ fn default_config_loads() {
         .to_string_lossy()
         .contains(&ramws::util::project_slug(&orig).unwrap()));
 }
+
+#[test]
+fn diff_lists_per_file_changes() {
+    let fx = Fixture::new("");
+    fx.write_disk("src/keep.rs", "keep");
+    fx.write_disk("src/edit.rs", "old");
+    fx.write_disk("src/drop.rs", "drop");
+    fx.ramws().arg("start").assert().success();
+    fs::write(fx.ws_root.join("src/new.rs"), "new").unwrap();
+    fs::write(fx.ws_root.join("src/edit.rs"), "newer").unwrap();
+    fs::remove_file(fx.ws_root.join("src/drop.rs")).unwrap();
+
+    fx.ramws()
+        .arg("diff")
+        .assert()
+        .success()
+        .stdout("D src/drop.rs\nM src/edit.rs\nA src/new.rs\n");
+    let output = fx.ramws().args(["diff", "--json"]).output().unwrap();
+    let changes: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(changes[1]["kind"], "modified");
+    assert_eq!(changes[1]["size_delta"], 2);
+    fx.ramws()
+        .args(["status"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("changed 1, added 1, deleted 1"));
+}
+
+#[test]
+fn diff_paths_resolve_against_the_working_dir() {
+    let fx = Fixture::new("");
+    fs::create_dir(fx.disk(".git")).unwrap();
+    fx.write_disk("src/edit.rs", "old");
+    fx.write_disk("docs/guide.md", "old");
+    fx.ramws().arg("start").assert().success();
+    fs::write(fx.ws_root.join("src/edit.rs"), "newer").unwrap();
+    fs::write(fx.ws_root.join("docs/guide.md"), "newer").unwrap();
+
+    fx.ramws()
+        .args(["diff", "./src"])
+        .assert()
+        .success()
+        .stdout("M src/edit.rs\n");
+    fx.ramws()
+        .arg("diff")
+        .arg(fx.disk("docs"))
+        .assert()
+        .success()
+        .stdout("M docs/guide.md\n");
+    #[allow(deprecated)]
+    let mut from_subdir = assert_cmd::Command::cargo_bin("ramws").unwrap();
+    from_subdir
+        .arg("--chdir")
+        .arg(fx.disk("src"))
+        .args(["diff", "edit.rs"])
+        .assert()
+        .success()
+        .stdout("M src/edit.rs\n");
+    fx.ramws()
+        .args(["diff", "/"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("outside the project"));
+}
+
+#[test]
+fn diff_patch_applies_with_git() {
+    let fx = Fixture::new("");
+    fx.write_disk("src/edit.rs", "one\ntwo\nthree\n");
+    fx.write_disk("src/drop.rs", "gone\n");
+    fx.write_disk("assets/logo.bin", "\0\x01");
+    fx.ramws().arg("start").assert().success();
+    fs::write(fx.ws_root.join("src/edit.rs"), "one\n2\nthree").unwrap();
+    fs::write(fx.ws_root.join("src/new.rs"), "fresh\n").unwrap();
+    fs::remove_file(fx.ws_root.join("src/drop.rs")).unwrap();
+    fs::write(fx.ws_root.join("assets/logo.bin"), "\0\x02").unwrap();
+
+    let output = fx.ramws().args(["diff", "--patch"]).output().unwrap();
+    let patch = String::from_utf8(output.stdout).unwrap();
+    assert!(patch.contains("Binary files a/assets/logo.bin and b/assets/logo.bin differ"));
+
+    let text_patch = fx
+        .ramws()
+        .args(["diff", "--patch", "src"])
+        .output()
+        .unwrap()
+        .stdout;
+    fs::write(fx.disk("ram.patch"), text_patch).unwrap();
+    let status = std::process::Command::new("git")
+        .current_dir(fx.project.path())
+        .args(["apply", "ram.patch"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(
+        fs::read_to_string(fx.disk("src/edit.rs")).unwrap(),
+        "one\n2\nthree"
+    );
+    assert_eq!(
+        fs::read_to_string(fx.disk("src/new.rs")).unwrap(),
+        "fresh\n"
+    );
+    assert!(!fx.disk("src/drop.rs").exists());
+}
+
+#[test]
+fn diff_patch_quotes_names_and_splits_type_changes() {
+    let fx = Fixture::new("");
+    fx.write_disk("my file.txt", "old\n");
+    fx.write_disk("say \"hi\".txt", "old\n");
+    fx.write_disk("swap", "was a file\n");
+    fx.ramws().arg("start").assert().success();
+    fs::write(fx.ws_root.join("my file.txt"), "new\n").unwrap();
+    fs::write(fx.ws_root.join("say \"hi\".txt"), "new\n").unwrap();
+    fs::write(fx.ws_root.join("caf\u{e9}.txt"), "fresh\n").unwrap();
+    fs::remove_file(fx.ws_root.join("swap")).unwrap();
+    std::os::unix::fs::symlink("my file.txt", fx.ws_root.join("swap")).unwrap();
+
+    let patch = fx
+        .ramws()
+        .args(["diff", "--patch"])
+        .output()
+        .unwrap()
+        .stdout;
+    let text = String::from_utf8(patch.clone()).unwrap();
+    assert!(text.contains("diff --git \"a/say \\\"hi\\\".txt\" \"b/say \\\"hi\\\".txt\""));
+    assert!(text.contains("+++ \"b/caf\\303\\251.txt\""));
+    assert!(text.contains("--- a/my file.txt\t"));
+    assert!(text.contains("deleted file mode 100644"));
+    assert!(text.contains("new file mode 120000"));
+
+    fs::write(fx.disk("ram.patch"), patch).unwrap();
+    let status = std::process::Command::new("git")
+        .current_dir(fx.project.path())
+        .args(["apply", "ram.patch"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(
+        fs::read_to_string(fx.disk("say \"hi\".txt")).unwrap(),
+        "new\n"
+    );
+    assert_eq!(
+        fs::read_to_string(fx.disk("caf\u{e9}.txt")).unwrap(),
+        "fresh\n"
+    );
+    assert_eq!(
+        fs::read_link(fx.disk("swap")).unwrap(),
+        std::path::Path::new("my file.txt")
+    );
+}
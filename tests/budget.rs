@@ -0,0 +1,53 @@
+mod common;
+use common::Fixture;
+
+#[test]
+fn start_refuses_sources_over_max_size() {
+    // The fixture opens the `workspace:` section; continue it.
+    let fx = Fixture::new("  max_size: 1K\nsources:\n  - path: src\n  - path: docs\n");
+    fx.write_disk("src/big.rs", &"x".repeat(3000));
+    fx.write_disk("docs/guide.md", "guide");
+    fx.ramws()
+        .arg("start")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "sources need an estimated 2.93 KiB",
+        ))
+        .stderr(predicates::str::contains("workspace.max_size"))
+        .stderr(predicates::str::contains("src   2.93 KiB"))
+        .stderr(predicates::str::contains("docs  5.00 B"));
+    assert!(!fx.ram("src/big.rs").exists());
+
+    let fx = Fixture::new("  max_size: 1K\n  over_budget: warn\n");
+    fx.write_disk("src/big.rs", &"x".repeat(3000));
+    fx.ramws()
+        .env_remove("RUST_LOG")
+        .arg("start")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(
+            "Warning: sources need an estimated",
+        ));
+    assert!(fx.ram("src/big.rs").exists());
+}
+
+#[test]
+fn status_warns_near_max_size() {
+    let fx = Fixture::new("  max_size: 2K\n");
+    fx.write_disk("src/a.rs", &"x".repeat(1900));
+    fx.ramws()
+        .env_remove("RUST_LOG")
+        .arg("start")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(
+            "Warning: sources need an estimated",
+        ));
+    fx.ramws()
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Warning: workspace uses"))
+        .stdout(predicates::str::contains("of its 2.00 KiB max_size"));
+}
@@ -0,0 +1,78 @@
+use std::fs;
+
+mod common;
+use common::Fixture;
+
+#[test]
+fn cache_dirs_are_seeded_and_saved() {
+    let fx = Fixture::new(
+        "build_dirs:\n  - path: target\n    type: cache\n  - path: tmp\n    type: scratch\n",
+    );
+    fx.write_disk("src/main.rs", "main");
+    fx.write_disk("target/debug/dep.rlib", "compiled");
+    fx.ramws().arg("start").assert().success();
+    assert_eq!(
+        fs::read_to_string(fx.ram("target/debug/dep.rlib")).unwrap(),
+        "compiled"
+    );
+    assert!(fx.ram("tmp").is_dir());
+
+    fs::write(fx.ram("target/debug/new.rlib"), "fresh").unwrap();
+    fs::write(fx.ram("tmp/junk"), "junk").unwrap();
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("target/debug/new.rlib"), "fresh");
+    assert!(!fx.disk("tmp/junk").exists());
+
+    fs::write(fx.ram("target/debug/last.rlib"), "last").unwrap();
+    fx.ramws()
+        .args(["--json", "status"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("\"ram_bytes\": 17"));
+    fx.ramws().args(["destroy", "--force"]).assert().success();
+    assert_eq!(fx.read_disk("target/debug/last.rlib"), "last");
+}
+
+#[test]
+fn cache_store_can_live_elsewhere() {
+    let fx = Fixture::new(
+        "build_dirs:\n  - path: .cache\n    type: cache\n    store: .ramws/cache-store\n",
+    );
+    fx.write_disk(".ramws/cache-store/blob", "stored");
+    fx.ramws().arg("start").assert().success();
+    assert_eq!(fs::read_to_string(fx.ram(".cache/blob")).unwrap(), "stored");
+    fs::write(fx.ram(".cache/blob"), "updated").unwrap();
+    fx.ramws().args(["destroy", "--force"]).assert().success();
+    assert_eq!(fx.read_disk(".ramws/cache-store/blob"), "updated");
+}
+
+#[test]
+fn linked_build_dirs_point_at_ram_until_destroy() {
+    let fx = Fixture::new(
+        "build_dirs:\n  - path: target\n    type: cache\n    link: true\n  - path: out\n    type: scratch\n    link: true\n",
+    );
+    fx.write_disk("target/dep.rlib", "compiled");
+    fx.write_disk("out/old.o", "old");
+    fx.ramws().arg("start").assert().success();
+    assert_eq!(fs::read_link(fx.disk("target")).unwrap(), fx.ram("target"));
+    assert_eq!(fs::read_link(fx.disk("out")).unwrap(), fx.ram("out"));
+    assert_eq!(fx.read_disk("target/dep.rlib"), "compiled");
+    assert!(!fx.disk("out/old.o").exists());
+
+    fx.write_disk("target/new.rlib", "fresh");
+    fx.write_disk("out/new.o", "scratch");
+    assert!(fx.ram("out/new.o").exists());
+    // A second start keeps the existing links.
+    fx.ramws().arg("start").assert().success();
+    fx.ramws().args(["destroy", "--force"]).assert().success();
+
+    assert!(!fs::symlink_metadata(fx.disk("target"))
+        .unwrap()
+        .is_symlink());
+    assert_eq!(fx.read_disk("target/new.rlib"), "fresh");
+    assert_eq!(fx.read_disk("out/old.o"), "old");
+    assert!(!fx.disk("out/new.o").exists());
+}
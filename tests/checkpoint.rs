@@ -0,0 +1,122 @@
+use std::fs;
+
+mod common;
+use common::Fixture;
+
+const CONFIG: &str = "checkpoint:\n  dir: .ramws/checkpoints\n  keep: 2\nbuild_dirs:\n  - path: build\n    type: scratch\n";
+
+#[test]
+fn checkpoints_copy_only_changed_files() {
+    let fx = Fixture::new(CONFIG);
+    fx.write_disk("src/a.rs", "a");
+    fx.write_disk("src/b.rs", "b");
+    fx.ramws().arg("start").assert().success();
+    fs::write(fx.ram("build/out.o"), "object").unwrap();
+
+    fx.ramws()
+        .arg("checkpoint")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("4 files copied"));
+    fx.ramws()
+        .arg("checkpoint")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("0 files copied"));
+    fs::write(fx.ram("src/a.rs"), "edited").unwrap();
+    fx.ramws()
+        .arg("checkpoint")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("1 files copied"));
+
+    let kept = fs::read_dir(fx.disk(".ramws/checkpoints"))
+        .unwrap()
+        .filter(|e| e.as_ref().unwrap().path().join("manifest.json").exists())
+        .count();
+    assert_eq!(kept, 2);
+}
+
+#[test]
+fn start_from_checkpoint_rehydrates_after_reboot() {
+    let fx = Fixture::new(CONFIG);
+    fx.write_disk("src/a.rs", "a");
+    fx.ramws().arg("start").assert().success();
+    fs::write(fx.ram("src/a.rs"), "unsynced").unwrap();
+    fs::write(fx.ram("build/out.o"), "object").unwrap();
+    fx.ramws().arg("checkpoint").assert().success();
+
+    // A reboot wipes the RAM workspace.
+    fs::remove_dir_all(&fx.ws_root).unwrap();
+    fx.ramws()
+        .args(["start", "--from-checkpoint"])
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(fx.ram("src/a.rs")).unwrap(), "unsynced");
+    assert_eq!(fs::read_to_string(fx.ram("build/out.o")).unwrap(), "object");
+
+    // Restored edits survive a later start and still show up as RAM changes.
+    fx.ramws().arg("start").assert().success();
+    assert_eq!(fs::read_to_string(fx.ram("src/a.rs")).unwrap(), "unsynced");
+    fx.ramws()
+        .arg("diff")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("M src/a.rs"));
+}
+
+#[test]
+fn restoring_an_older_checkpoint_does_not_revert_later_syncs() {
+    let fx = Fixture::new(CONFIG);
+    fx.write_disk("src/a.rs", "a");
+    fx.ramws().arg("start").assert().success();
+    fx.ramws().arg("checkpoint").assert().success();
+    fs::write(fx.ram("src/a.rs"), "newer").unwrap();
+    fs::write(fx.ram("src/extra.rs"), "extra").unwrap();
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+
+    fx.ramws()
+        .args(["start", "--from-checkpoint"])
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(fx.ram("src/a.rs")).unwrap(), "a");
+    assert!(!fx.ram("src/extra.rs").exists());
+
+    // The older RAM copy is not an edit, so disk keeps the synced version.
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("src/a.rs"), "newer");
+    assert_eq!(fx.read_disk("src/extra.rs"), "extra");
+}
+
+#[test]
+fn pruning_leaves_unrelated_files_in_the_checkpoint_dir() {
+    let fx = Fixture::new("checkpoint:\n  dir: .ramws/shared\n  keep: 1\n");
+    fx.write_disk("src/a.rs", "a");
+    fx.write_disk(".ramws/shared/notes.txt", "mine");
+    fx.write_disk(".ramws/shared/other/x", "mine");
+    fx.write_disk(".ramws/shared/20200101T000000Z/readme", "mine");
+    fx.ramws().arg("start").assert().success();
+
+    for edit in ["one", "two", "three"] {
+        fs::write(fx.ram("src/a.rs"), edit).unwrap();
+        fx.ramws().arg("checkpoint").assert().success();
+    }
+
+    assert_eq!(fx.read_disk(".ramws/shared/notes.txt"), "mine");
+    assert_eq!(fx.read_disk(".ramws/shared/other/x"), "mine");
+    assert_eq!(
+        fx.read_disk(".ramws/shared/20200101T000000Z/readme"),
+        "mine"
+    );
+    let kept = fs::read_dir(fx.disk(".ramws/shared"))
+        .unwrap()
+        .filter(|e| e.as_ref().unwrap().path().join("manifest.json").exists())
+        .count();
+    assert_eq!(kept, 1);
+}
@@ -0,0 +1,56 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+use tempfile::{tempdir, TempDir};
+
+/// A project on disk plus a workspace root under a separate temp dir, so tests
+/// never touch `/dev/shm`.
+pub struct Fixture {
+    pub project: TempDir,
+    _ram: TempDir,
+    pub ws_root: PathBuf,
+}
+
+impl Fixture {
+    pub fn new(extra_config: &str) -> Self {
+        let project = tempdir().unwrap();
+        let ram = tempdir().unwrap();
+        let ws_root = ram.path().join("ws");
+        fs::write(
+            project.path().join(".ramws.yml"),
+            format!("workspace:\n  root: {}\n{extra_config}", ws_root.display()),
+        )
+        .unwrap();
+        Fixture {
+            project,
+            _ram: ram,
+            ws_root,
+        }
+    }
+
+    pub fn disk(&self, rel: &str) -> PathBuf {
+        self.project.path().join(rel)
+    }
+
+    pub fn write_disk(&self, rel: &str, body: &str) {
+        let path = self.disk(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, body).unwrap();
+    }
+
+    pub fn ram(&self, rel: &str) -> PathBuf {
+        self.ws_root.join(rel)
+    }
+
+    pub fn read_disk(&self, rel: &str) -> String {
+        fs::read_to_string(self.disk(rel)).unwrap()
+    }
+
+    pub fn ramws(&self) -> assert_cmd::Command {
+        #[allow(deprecated)]
+        let mut cmd = assert_cmd::Command::cargo_bin("ramws").unwrap();
+        cmd.arg("--chdir").arg(self.project.path());
+        cmd
+    }
+}
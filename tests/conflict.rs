@@ -0,0 +1,105 @@
+use predicates::prelude::*;
+use std::fs;
+
+mod common;
+use common::Fixture;
+
+fn started(extra_config: &str) -> Fixture {
+    let fx = Fixture::new(extra_config);
+    fx.write_disk("src/shared.rs", "base");
+    fx.write_disk("src/ram.rs", "base");
+    fx.write_disk("src/disk.rs", "base");
+    fx.ramws().arg("start").assert().success();
+    fx
+}
+
+#[test]
+fn conflicting_edits_are_refused_by_default() {
+    let fx = started("");
+    fs::write(fx.ram("src/shared.rs"), "ram edit").unwrap();
+    fx.write_disk("src/shared.rs", "disk edit!");
+
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("src/shared.rs"));
+    assert_eq!(fx.read_disk("src/shared.rs"), "disk edit!");
+}
+
+#[test]
+fn disk_only_edits_survive_sync_back() {
+    let fx = started("");
+    fs::write(fx.ram("src/ram.rs"), "ram edit").unwrap();
+    fx.write_disk("src/disk.rs", "disk edit!");
+    fx.write_disk("src/created_on_disk.rs", "new");
+
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("src/ram.rs"), "ram edit");
+    assert_eq!(fx.read_disk("src/disk.rs"), "disk edit!");
+    assert!(fx.disk("src/created_on_disk.rs").exists());
+}
+
+#[test]
+fn keep_both_writes_suffixed_copies() {
+    let fx = started("sync:\n  conflict: keep-both\n");
+    fs::write(fx.ram("src/shared.rs"), "ram edit").unwrap();
+    fx.write_disk("src/shared.rs", "disk edit!");
+
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("src/shared.rs"), "disk edit!");
+    assert_eq!(fx.read_disk("src/shared.rs.ramws-ours"), "ram edit");
+    assert_eq!(fx.read_disk("src/shared.rs.ramws-theirs"), "disk edit!");
+}
+
+#[test]
+fn theirs_pulls_disk_copy_into_ram() {
+    let fx = started("sync:\n  conflict: theirs\n");
+    fs::write(fx.ram("src/shared.rs"), "ram edit").unwrap();
+    fx.write_disk("src/shared.rs", "disk edit!");
+
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("src/shared.rs"), "disk edit!");
+    assert_eq!(
+        fs::read_to_string(fx.ram("src/shared.rs")).unwrap(),
+        "disk edit!"
+    );
+    fx.ramws().arg("diff").assert().success().stdout("");
+}
+
+#[test]
+fn noninteractive_prompt_uses_configured_fallback() {
+    let fx = started("sync:\n  conflict: prompt\n  noninteractive_conflict: ours\n");
+    fs::write(fx.ram("src/shared.rs"), "ram edit").unwrap();
+    fx.write_disk("src/shared.rs", "disk edit!");
+
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("applying conflict policy Ours"))
+        .stderr(predicate::str::contains("  src/shared.rs"));
+    assert_eq!(fx.read_disk("src/shared.rs"), "ram edit");
+}
+
+#[test]
+fn noninteractive_prompt_refuses_without_fallback() {
+    let fx = started("sync:\n  conflict: prompt\n");
+    fs::write(fx.ram("src/shared.rs"), "ram edit").unwrap();
+    fx.write_disk("src/shared.rs", "disk edit!");
+
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .failure();
+    assert_eq!(fx.read_disk("src/shared.rs"), "disk edit!");
+}
@@ -0,0 +1,319 @@
+use predicates::prelude::*;
+use std::fs;
+use std::process::Command;
+
+mod common;
+use common::Fixture;
+
+fn git(fx: &Fixture, args: &[&str]) {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(fx.project.path())
+        .args([
+            "-c",
+            "user.name=ramws",
+            "-c",
+            "user.email=ramws@example.com",
+        ])
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {args:?} failed");
+}
+
+fn committed(extra_config: &str) -> Fixture {
+    let fx = Fixture::new(&format!("git:\n  require_clean: true\n{extra_config}"));
+    fx.write_disk("a.txt", "a");
+    fx.write_disk("src/b.txt", "b");
+    git(&fx, &["init", "-q"]);
+    git(&fx, &["add", "-A"]);
+    git(&fx, &["commit", "-q", "-m", "init"]);
+    fx
+}
+
+#[test]
+fn start_refuses_a_dirty_tree() {
+    let fx = committed("");
+    fx.write_disk("src/b.txt", "edited");
+    fx.ramws()
+        .arg("start")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("1 files have uncommitted changes"))
+        .stderr(predicate::str::contains("src/b.txt"));
+    assert!(!fx.ram("a.txt").exists());
+
+    // Without a git binary the index is read directly.
+    fx.ramws()
+        .env("PATH", "/nonexistent")
+        .arg("start")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("src/b.txt"));
+
+    fx.ramws()
+        .args(["start", "--ignore-dirty"])
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(fx.ram("src/b.txt")).unwrap(), "edited");
+}
+
+#[test]
+fn sync_back_refuses_to_overwrite_dirty_files() {
+    let fx = committed("sync:\n  conflict: ours\n");
+    fx.ramws()
+        .env("PATH", "/nonexistent")
+        .arg("start")
+        .assert()
+        .success();
+    fs::write(fx.ram("a.txt"), "from ram").unwrap();
+    fs::write(fx.ram("src/b.txt"), "from ram").unwrap();
+    fx.write_disk("src/b.txt", "uncommitted");
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("src/b.txt"))
+        .stderr(predicate::str::contains("a.txt\n").not());
+    assert_eq!(fx.read_disk("a.txt"), "a");
+    assert_eq!(fx.read_disk("src/b.txt"), "uncommitted");
+
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive", "--ignore-dirty"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("src/b.txt"), "from ram");
+
+    // Files ramws wrote itself do not count as dirty on the next sync.
+    fs::write(fx.ram("src/b.txt"), "again").unwrap();
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("src/b.txt"), "again");
+}
+
+#[test]
+fn sync_back_stages_exactly_what_it_synced() {
+    let fx = Fixture::new("git:\n  auto_stage_synced: true\n");
+    fx.write_disk("a.txt", "a");
+    fx.write_disk("src/b.txt", "b");
+    fx.write_disk("old.txt", "a file that gets renamed in RAM");
+    fx.write_disk("untouched.txt", "u");
+    git(&fx, &["init", "-q"]);
+    git(&fx, &["add", "-A"]);
+    git(&fx, &["commit", "-q", "-m", "init"]);
+    fx.ramws().arg("start").assert().success();
+    // Pending work of the user's own must stay unstaged.
+    fx.write_disk("untouched.txt", "user edit");
+
+    fs::write(fx.ram("a.txt"), "edited").unwrap();
+    fs::write(fx.ram("new.txt"), "new").unwrap();
+    fs::remove_file(fx.ram("src/b.txt")).unwrap();
+    fs::rename(fx.ram("old.txt"), fx.ram("moved.txt")).unwrap();
+    fx.ramws()
+        .env("RUST_LOG", "info")
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("staged 4 synced changes"))
+        .stdout(predicate::str::contains("R old.txt -> moved.txt"))
+        .stdout(predicate::str::contains("D src/b.txt"));
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(fx.project.path())
+        .args(["diff", "--cached", "--name-status", "--no-renames"])
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "M\ta.txt\nA\tmoved.txt\nA\tnew.txt\nD\told.txt\nD\tsrc/b.txt\n"
+    );
+}
+
+fn selected(select: &str) -> Fixture {
+    let fx = Fixture::new(&format!("sources:\n  - path: .\n    select: {select}\n"));
+    fx.write_disk(".gitignore", "*.log\n");
+    fx.write_disk("a.txt", "a");
+    fx.write_disk("src/b.txt", "b");
+    git(&fx, &["init", "-q"]);
+    git(&fx, &["add", "-A"]);
+    git(&fx, &["commit", "-q", "-m", "init"]);
+    fx.write_disk("notes.txt", "untracked");
+    fx.write_disk("debug.log", "ignored");
+    fx
+}
+
+#[test]
+fn git_tracked_source_mirrors_only_the_index() {
+    let fx = selected("git-tracked");
+    fx.ramws().arg("start").assert().success();
+    assert!(fx.ram("src/b.txt").exists());
+    assert!(!fx.ram("notes.txt").exists());
+    assert!(!fx.ram("debug.log").exists());
+    assert!(!fx.ram(".git").exists());
+
+    fs::write(fx.ram("a.txt"), "edited").unwrap();
+    fs::write(fx.ram("scratch.txt"), "not tracked").unwrap();
+    fx.ramws()
+        .arg("diff")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("a.txt"))
+        .stdout(predicate::str::contains("notes.txt").not())
+        .stdout(predicate::str::contains("scratch.txt").not());
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("a.txt"), "edited");
+    assert!(!fx.disk("scratch.txt").exists());
+    // Untracked files on disk are outside the source, not deletions.
+    assert_eq!(fx.read_disk("notes.txt"), "untracked");
+    assert_eq!(fx.read_disk("debug.log"), "ignored");
+}
+
+#[test]
+fn untracked_not_ignored_files_join_the_source() {
+    let fx = selected("git-tracked+untracked-not-ignored");
+    fx.ramws().arg("start").assert().success();
+    assert!(fx.ram("notes.txt").exists());
+    assert!(!fx.ram("debug.log").exists());
+
+    fs::write(fx.ram("src/fresh.rs"), "new").unwrap();
+    fs::write(fx.ram("src/trace.log"), "ignored").unwrap();
+    fx.ramws()
+        .arg("diff")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("src/fresh.rs"))
+        .stdout(predicate::str::contains("trace.log").not())
+        .stdout(predicate::str::contains("debug.log").not());
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("src/fresh.rs"), "new");
+    assert!(!fx.disk("src/trace.log").exists());
+    assert_eq!(fx.read_disk("debug.log"), "ignored");
+}
+
+#[test]
+fn worktree_mode_runs_git_in_ram_and_destroy_prunes_it() {
+    let fx = Fixture::new("git:\n  mode: worktree\n");
+    fx.write_disk("a.txt", "a");
+    fx.write_disk("src/b.txt", "b");
+    git(&fx, &["init", "-q"]);
+    git(&fx, &["add", "-A"]);
+    git(&fx, &["commit", "-q", "-m", "init"]);
+    fx.ramws().arg("start").assert().success();
+    assert!(fx.ram(".git").is_file());
+
+    fs::write(fx.ram("a.txt"), "edited").unwrap();
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(fx.ram(""))
+        .args(["status", "--porcelain"])
+        .output()
+        .unwrap();
+    assert!(status.status.success());
+    assert_eq!(String::from_utf8(status.stdout).unwrap(), " M a.txt\n");
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("a.txt"), "edited");
+    // The worktree's `.git` file never replaces the repository.
+    assert!(fx.disk(".git").is_dir());
+
+    // Commits made in RAM sit on a detached HEAD; destroy keeps them from
+    // being pruned until a branch holds them.
+    let commit = Command::new("git")
+        .arg("-C")
+        .arg(fx.ram(""))
+        .args([
+            "-c",
+            "user.name=ramws",
+            "-c",
+            "user.email=ramws@example.com",
+        ])
+        .args(["commit", "-qam", "in ram"])
+        .status()
+        .unwrap();
+    assert!(commit.success());
+    fx.ramws()
+        .args(["destroy", "--noninteractive"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no branch or tag contains"));
+    assert!(fx.ram(".git").is_file());
+    let branch = Command::new("git")
+        .arg("-C")
+        .arg(fx.ram(""))
+        .args(["branch", "from-ram"])
+        .status()
+        .unwrap();
+    assert!(branch.success());
+
+    fx.ramws().args(["destroy", "--force"]).assert().success();
+    let kept = Command::new("git")
+        .arg("-C")
+        .arg(fx.project.path())
+        .args(["log", "-1", "--format=%s", "from-ram"])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(kept.stdout).unwrap(), "in ram\n");
+    let list = Command::new("git")
+        .arg("-C")
+        .arg(fx.project.path())
+        .args(["worktree", "list", "--porcelain"])
+        .output()
+        .unwrap();
+    let list = String::from_utf8(list.stdout).unwrap();
+    assert_eq!(list.matches("worktree ").count(), 1, "{list}");
+}
+
+#[test]
+fn worktree_mode_leaves_other_stale_worktrees_registered() {
+    let fx = Fixture::new("git:\n  mode: worktree\n");
+    fx.write_disk("a.txt", "a");
+    git(&fx, &["init", "-q"]);
+    git(&fx, &["add", "-A"]);
+    git(&fx, &["commit", "-q", "-m", "init"]);
+    // The user's own worktree, on a drive that happens to be unplugged.
+    let elsewhere = tempfile::tempdir().unwrap();
+    let theirs = elsewhere.path().join("theirs");
+    git(
+        &fx,
+        &[
+            "worktree",
+            "add",
+            "-q",
+            "--detach",
+            theirs.to_str().unwrap(),
+        ],
+    );
+    fs::remove_dir_all(&theirs).unwrap();
+    let registered = || {
+        let list = Command::new("git")
+            .arg("-C")
+            .arg(fx.project.path())
+            .args(["worktree", "list", "--porcelain"])
+            .output()
+            .unwrap();
+        String::from_utf8(list.stdout).unwrap()
+    };
+
+    fx.ramws().arg("start").assert().success();
+    // The workspace vanishes with its tmpfs and is started again.
+    fs::remove_dir_all(fx.ram("")).unwrap();
+    fx.ramws().arg("start").assert().success();
+    assert!(fx.ram(".git").is_file());
+    assert_eq!(registered().matches("worktree ").count(), 3);
+
+    fx.ramws().args(["destroy", "--force"]).assert().success();
+    let list = registered();
+    assert_eq!(list.matches("worktree ").count(), 2, "{list}");
+    assert!(list.contains(theirs.to_str().unwrap()), "{list}");
+}
@@ -0,0 +1,81 @@
+use predicates::prelude::*;
+use std::fs;
+use std::process::Command;
+
+mod common;
+use common::Fixture;
+
+#[test]
+fn ignore_files_keep_paths_out_of_ram_and_off_disk() {
+    let fx = Fixture::new(
+        "sources:\n  - path: .\n    exclude:\n      - .git/**\n    ignore_files: true\n",
+    );
+    let status = Command::new("git")
+        .arg("init")
+        .arg("-q")
+        .arg(fx.project.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    fx.write_disk(".git/info/exclude", "secret.txt\n");
+    fx.write_disk(".gitignore", "*.log\n!keep.log\n/gen/\n");
+    fx.write_disk("sub/.gitignore", "local.txt\n");
+    fx.write_disk(".ramwsignore", "big/\n");
+    for rel in [
+        "a.txt",
+        "x.log",
+        "keep.log",
+        "gen/out.c",
+        "nested/gen/file.c",
+        "sub/local.txt",
+        "sub/other.txt",
+        "big/blob",
+        "secret.txt",
+    ] {
+        fx.write_disk(rel, rel);
+    }
+
+    fx.ramws().arg("start").assert().success();
+    for kept in [
+        "a.txt",
+        "keep.log",
+        "nested/gen/file.c",
+        "sub/other.txt",
+        ".gitignore",
+        ".ramwsignore",
+    ] {
+        assert!(fx.ram(kept).exists(), "{kept} should be mirrored");
+    }
+    for skipped in ["x.log", "gen", "sub/local.txt", "big", "secret.txt"] {
+        assert!(!fx.ram(skipped).exists(), "{skipped} should be ignored");
+    }
+
+    fs::write(fx.ram("a.txt"), "edited").unwrap();
+    fs::write(fx.ram("new.log"), "ignored").unwrap();
+    fx.ramws()
+        .arg("diff")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("a.txt"))
+        .stdout(predicate::str::contains("new.log").not())
+        .stdout(predicate::str::contains("x.log").not());
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("a.txt"), "edited");
+    assert!(!fx.disk("new.log").exists());
+    // Ignored files on disk are outside the source, not deletions.
+    assert!(fx.disk("x.log").exists());
+    assert!(fx.disk("gen/out.c").exists());
+    assert!(fx.disk("big/blob").exists());
+}
+
+#[test]
+fn ignore_files_are_off_unless_enabled() {
+    let fx = Fixture::new("");
+    fx.write_disk(".gitignore", "*.log\n");
+    fx.write_disk("x.log", "log");
+    fx.ramws().arg("start").assert().success();
+    assert!(fx.ram("x.log").exists());
+}
@@ -0,0 +1,67 @@
+use std::fs;
+
+mod common;
+use common::Fixture;
+
+#[test]
+fn lazy_start_keeps_unfetched_files_on_disk() {
+    let fx = Fixture::new("sources:\n  - path: .\n    hot:\n      - \"*.rs\"\n");
+    fx.write_disk("src/main.rs", "main");
+    fx.write_disk("docs/guide.md", "guide");
+    fx.write_disk("assets/logo.svg", "logo");
+    fx.ramws()
+        .args(["start", "--lazy"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("left 3 files on disk"));
+    assert!(fx.ram("src/main.rs").exists());
+    assert!(fx.ram("docs").is_dir());
+    assert!(!fx.ram("docs/guide.md").exists());
+    fx.ramws()
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("deleted 0"))
+        .stdout(predicates::str::contains("Lazy: 3 files"));
+
+    // Read-only commands leave lazy.json alone, even once a pending path
+    // exists in RAM.
+    fs::write(fx.ram("docs/guide.md"), "created").unwrap();
+    let lazy_json = fx.disk(".ramws/lazy.json");
+    let before = fs::read_to_string(&lazy_json).unwrap();
+    fx.ramws().arg("status").assert().success();
+    fx.ramws().arg("diff").assert().success();
+    assert_eq!(fs::read_to_string(&lazy_json).unwrap(), before);
+    fs::remove_file(fx.ram("docs/guide.md")).unwrap();
+
+    fs::write(fx.ram("src/main.rs"), "edited").unwrap();
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("src/main.rs"), "edited");
+    assert_eq!(fx.read_disk("docs/guide.md"), "guide");
+
+    // Pulling disk changes leaves unfetched files on disk.
+    fx.write_disk("assets/logo.svg", "new logo");
+    fx.ramws().args(["sync", "--from"]).assert().success();
+    assert!(!fx.ram("assets/logo.svg").exists());
+
+    fx.ramws()
+        .args(["fetch", "docs"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("fetched 1 files"));
+    assert_eq!(
+        fs::read_to_string(fx.ram("docs/guide.md")).unwrap(),
+        "guide"
+    );
+    // Once fetched, deleting it in RAM is a real deletion.
+    fs::remove_file(fx.ram("docs/guide.md")).unwrap();
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert!(!fx.disk("docs/guide.md").exists());
+    assert_eq!(fx.read_disk("assets/logo.svg"), "new logo");
+}
@@ -0,0 +1,28 @@
+use std::fs;
+
+mod common;
+use common::Fixture;
+
+#[test]
+fn workspace_gets_its_own_tmpfs() {
+    if !nix::unistd::Uid::effective().is_root() {
+        eprintln!("skipping: mounting tmpfs needs root");
+        return;
+    }
+    // The fixture opens the `workspace:` section; continue it.
+    let fx = Fixture::new("  mount:\n    size: 8m\n    nr_inodes: 1000\n");
+    fx.write_disk("src/main.rs", "main");
+    fx.ramws().arg("start").assert().success();
+    assert!(ramws::mount::is_mount_point(&fx.ram("")));
+    assert_eq!(fs::read_to_string(fx.ram("src/main.rs")).unwrap(), "main");
+
+    fx.ramws()
+        .args(["--json", "status"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("\"total\": \"8.00 MiB\""))
+        .stdout(predicates::str::contains("\"mounted\": true"))
+        .stdout(predicates::str::contains("\"inodes_total\": 1000"));
+    fx.ramws().args(["destroy", "--force"]).assert().success();
+    assert!(!fx.ram("").exists());
+}
@@ -0,0 +1,171 @@
+use predicates::prelude::*;
+use std::fs;
+use std::process::Command;
+
+mod common;
+use common::Fixture;
+
+#[test]
+fn sync_back_applies_the_upper_layer() {
+    if !nix::unistd::Uid::effective().is_root() {
+        eprintln!("skipping: the kernel overlay needs root here");
+        return;
+    }
+    // The fixture opens the `workspace:` section; continue it.
+    let fx = Fixture::new("  mode: overlay\nsync:\n  protect:\n    - keep.local\n");
+    fx.write_disk("a.txt", "a");
+    fx.write_disk("b.txt", "b");
+    fx.write_disk("keep.local", "local");
+    fx.write_disk("old/c.txt", "c");
+    fx.write_disk("swap/d.txt", "d");
+    fx.write_disk(".git/HEAD", "ref");
+    fx.ramws().arg("start").assert().success();
+    assert!(ramws::mount::is_mount_point(&fx.ram("")));
+    assert_eq!(fs::read_to_string(fx.ram("a.txt")).unwrap(), "a");
+
+    fs::write(fx.ram("a.txt"), "edited").unwrap();
+    fs::write(fx.ram("new.txt"), "new").unwrap();
+    fs::remove_file(fx.ram("b.txt")).unwrap();
+    fs::remove_file(fx.ram("keep.local")).unwrap();
+    fs::remove_dir_all(fx.ram("old")).unwrap();
+    // Recreating a deleted directory makes it opaque.
+    fs::remove_dir_all(fx.ram("swap")).unwrap();
+    fs::create_dir(fx.ram("swap")).unwrap();
+    fs::write(fx.ram("swap/e.txt"), "e").unwrap();
+    fs::write(fx.ram(".git/HEAD"), "changed").unwrap();
+
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("a.txt"), "edited");
+    assert_eq!(fx.read_disk("new.txt"), "new");
+    assert!(!fx.disk("b.txt").exists());
+    assert_eq!(fx.read_disk("keep.local"), "local");
+    assert!(!fx.disk("old").exists());
+    assert!(!fx.disk("swap/d.txt").exists());
+    assert_eq!(fx.read_disk("swap/e.txt"), "e");
+    assert_eq!(fx.read_disk(".git/HEAD"), "ref");
+
+    // Applied entries leave the upper layer, so the overlay is mounted again
+    // and RAM shows the disk copies.
+    assert!(ramws::mount::is_mount_point(&fx.ram("")));
+    let upper = fx.ram("").with_file_name("ws.overlay").join("upper");
+    assert!(!upper.join("a.txt").exists());
+    assert!(!upper.join("old").exists());
+    assert_eq!(fs::read_to_string(fx.ram("a.txt")).unwrap(), "edited");
+    assert!(!fx.ram("keep.local").exists());
+    fx.ramws()
+        .arg("status")
+        .assert()
+        .success()
+        // keep.local was protected, so it stays deleted in RAM only.
+        .stdout(predicate::str::contains("changed 0, added 0, deleted 1"));
+
+    fx.ramws().args(["destroy", "--force"]).assert().success();
+    assert!(!fx.ram("").exists());
+    assert!(!fx.ram("").with_file_name("ws.overlay").exists());
+}
+
+#[test]
+fn sync_back_keeps_disk_edits_made_after_copy_up() {
+    if !nix::unistd::Uid::effective().is_root() {
+        eprintln!("skipping: the kernel overlay needs root here");
+        return;
+    }
+    let fx = Fixture::new("  mode: overlay\n");
+    fx.write_disk("a.txt", "a");
+    fx.write_disk("b.txt", "b");
+    fx.ramws().arg("start").assert().success();
+    fs::write(fx.ram("a.txt"), "ram").unwrap();
+    fs::write(fx.ram("b.txt"), "ram").unwrap();
+    fx.write_disk("a.txt", "disk");
+
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("a.txt"));
+    assert_eq!(fx.read_disk("a.txt"), "disk");
+    assert_eq!(fx.read_disk("b.txt"), "b");
+    assert!(ramws::mount::is_mount_point(&fx.ram("")));
+    assert_eq!(fs::read_to_string(fx.ram("a.txt")).unwrap(), "ram");
+
+    // Nothing is written while the workspace is in use.
+    let mut busy = Command::new("sleep")
+        .arg("30")
+        .current_dir(fx.ram(""))
+        .spawn()
+        .unwrap();
+    let output = fx
+        .ramws()
+        .args(["sync", "--back", "--only", "b.txt"])
+        .output()
+        .unwrap();
+    busy.kill().unwrap();
+    busy.wait().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("is in use"), "{stderr}");
+    assert_eq!(fx.read_disk("b.txt"), "b");
+
+    fx.ramws()
+        .args(["sync", "--back", "--only", "b.txt"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("b.txt"), "ram");
+    fx.ramws().args(["destroy", "--force"]).assert().success();
+}
+
+#[test]
+fn a_lost_mount_keeps_its_upper_layer() {
+    if !nix::unistd::Uid::effective().is_root() {
+        eprintln!("skipping: the kernel overlay needs root here");
+        return;
+    }
+    let fx = Fixture::new("  mode: overlay\n");
+    fx.write_disk("a.txt", "a");
+    fx.write_disk("b.txt", "b");
+    fx.ramws().arg("start").assert().success();
+    fs::write(fx.ram("a.txt"), "edited").unwrap();
+    // What a namespaced shell leaves behind when it exits.
+    nix::mount::umount2(&fx.ram(""), nix::mount::MntFlags::MNT_DETACH).unwrap();
+
+    fx.ramws()
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("changed 1, added 0, deleted 0"))
+        .stdout(predicate::str::contains("overlay is not mounted"));
+    fx.ramws()
+        .args(["destroy", "--noninteractive"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not mounted"));
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("a.txt"), "edited");
+    assert_eq!(fx.read_disk("b.txt"), "b");
+    fx.ramws().arg("destroy").assert().success();
+}
+
+#[test]
+fn watch_and_continuous_sync_are_refused() {
+    // Refused before anything is mounted, so this needs no root.
+    let fx = Fixture::new("  mode: overlay\nsync:\n  continuous: true\n");
+    fx.write_disk("a.txt", "a");
+
+    fx.ramws()
+        .arg("watch")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("workspace.mode: overlay"));
+    fx.ramws()
+        .args(["shell", "--noninteractive", "--", "true"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("workspace.mode: overlay"));
+    assert!(!fx.ram("").exists());
+}
@@ -0,0 +1,89 @@
+use ramws::config::{Config, PressureConfig};
+use ramws::pressure::{spill_largest, Monitor, Sample};
+use std::fs;
+use std::time::{Duration, Instant};
+
+mod common;
+use common::Fixture;
+
+const PSI: &str = "some avg10=31.50 avg60=12.00 avg300=3.00 total=1234\n\
+                   full avg10=8.00 avg60=2.00 avg300=0.50 total=567\n";
+const MEMINFO: &str = "MemTotal:        8000000 kB\nMemFree:          100000 kB\n\
+                       MemAvailable:     400000 kB\n";
+
+#[test]
+fn samples_parse_and_flag_high_pressure() {
+    let sample = Sample::parse(PSI, MEMINFO);
+    assert_eq!(sample.some_avg10, Some(31.5));
+    assert_eq!(sample.mem_total, Some(8_000_000 * 1024));
+    assert_eq!(sample.mem_available, Some(400_000 * 1024));
+    let cfg = PressureConfig::default();
+    assert!(sample.is_high(&cfg));
+    // Without PSI, 5% available is still below the 10% default.
+    assert!(Sample::parse("", MEMINFO).is_high(&cfg));
+    let calm = Sample::parse("some avg10=1.00 avg60=0 avg300=0 total=1\n", "");
+    assert!(!calm.is_high(&cfg));
+
+    let mut monitor = Monitor::default();
+    let start = Instant::now();
+    let sustain = Duration::from_secs(30);
+    assert!(!monitor.observe(true, start, sustain));
+    assert!(!monitor.observe(true, start + Duration::from_secs(20), sustain));
+    assert!(monitor.observe(true, start + Duration::from_secs(30), sustain));
+    assert!(!monitor.observe(false, start + Duration::from_secs(40), sustain));
+    assert!(!monitor.observe(true, start + Duration::from_secs(50), sustain));
+}
+
+#[test]
+fn largest_scratch_dir_spills_to_disk() {
+    let fx = Fixture::new(
+        "build_dirs:\n  - path: small\n  - path: big\n  - path: cache\n    type: cache\n\
+         pressure:\n  spill: largest-scratch\n  spill_dir: .ramws/spill\n",
+    );
+    fx.write_disk("src/main.rs", "main");
+    fx.ramws().arg("start").assert().success();
+    fs::write(fx.ram("small/a.o"), "a").unwrap();
+    fs::write(fx.ram("big/b.o"), "b".repeat(100)).unwrap();
+    fs::write(fx.ram("cache/c"), "c".repeat(1000)).unwrap();
+    let cfg =
+        Config::load_from_file(&fx.disk(".ramws.yml"), fx.project.path().to_path_buf()).unwrap();
+
+    let (path, size) = spill_largest(&cfg).unwrap().unwrap();
+    assert_eq!((path.to_str().unwrap(), size), ("big", 100));
+    assert_eq!(
+        fs::read_link(fx.ram("big")).unwrap(),
+        fx.disk(".ramws/spill/big")
+    );
+    assert_eq!(
+        fs::read_to_string(fx.ram("big/b.o")).unwrap(),
+        "b".repeat(100)
+    );
+    assert!(!fx.ram("big.ramws-spill").exists());
+    let (path, _) = spill_largest(&cfg).unwrap().unwrap();
+    assert_eq!(path.to_str().unwrap(), "small");
+    assert!(spill_largest(&cfg).unwrap().is_none());
+
+    fx.ramws().args(["destroy", "--force"]).assert().success();
+    assert!(!fx.disk(".ramws/spill/big").exists());
+    assert!(!fx.disk(".ramws/spill/small").exists());
+}
+
+#[test]
+fn destroy_keeps_unrelated_files_in_the_spill_dir() {
+    let fx = Fixture::new(
+        "build_dirs:\n  - path: big\n\
+         pressure:\n  spill: largest-scratch\n  spill_dir: shared\n",
+    );
+    fx.write_disk("src/main.rs", "main");
+    fx.write_disk("shared/notes.txt", "mine");
+    fx.ramws().arg("start").assert().success();
+    fs::write(fx.ram("big/b.o"), "b").unwrap();
+    let cfg =
+        Config::load_from_file(&fx.disk(".ramws.yml"), fx.project.path().to_path_buf()).unwrap();
+    spill_largest(&cfg).unwrap().unwrap();
+    assert!(fx.disk("shared/big/b.o").exists());
+
+    fx.ramws().args(["destroy", "--force"]).assert().success();
+    assert!(!fx.disk("shared/big").exists());
+    assert_eq!(fx.read_disk("shared/notes.txt"), "mine");
+}
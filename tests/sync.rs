@@ -0,0 +1,339 @@
+use anyhow::Result;
+use ramws::config::SyncConfig;
+use ramws::native::NativeBackend;
+use ramws::syncer::{
+    diff_path, sync_path, ChangeKind, FileChange, FileType, SyncBackend, SyncOptions,
+};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tempfile::tempdir;
+
+#[test]
+fn native_sync_mirrors_tree_with_excludes_and_deletes() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::create_dir_all(src.path().join("src/nested")).unwrap();
+    fs::create_dir_all(src.path().join("target/debug")).unwrap();
+    fs::write(src.path().join("src/nested/lib.rs"), "fn main() {}").unwrap();
+    fs::write(src.path().join("run.sh"), "#!/bin/sh").unwrap();
+    fs::set_permissions(src.path().join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(src.path().join("target/debug/out"), "binary").unwrap();
+    fs::write(dst.path().join("stale.txt"), "old").unwrap();
+    fs::create_dir_all(dst.path().join("target")).unwrap();
+    fs::write(dst.path().join("target/keep"), "excluded").unwrap();
+
+    let opts = SyncOptions {
+        exclude: vec!["target/**".to_string()],
+        ..SyncOptions::from_config(&SyncConfig::default())
+    };
+    sync_path(&NativeBackend, src.path(), dst.path(), &opts).unwrap();
+
+    assert_eq!(
+        fs::read_to_string(dst.path().join("src/nested/lib.rs")).unwrap(),
+        "fn main() {}"
+    );
+    let mode = fs::metadata(dst.path().join("run.sh"))
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o755);
+    assert!(!dst.path().join("stale.txt").exists());
+    assert!(!dst.path().join("target/debug/out").exists());
+    assert!(dst.path().join("target/keep").exists());
+
+    let summary = diff_path(&NativeBackend, src.path(), dst.path(), &opts).unwrap();
+    assert_eq!(summary.added + summary.changed + summary.deleted, 0);
+}
+
+#[test]
+fn native_sync_skips_specials_and_sets_dir_modes_last() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    nix::unistd::mkfifo(&src.path().join("pipe"), nix::sys::stat::Mode::S_IRWXU).unwrap();
+    fs::create_dir(src.path().join("ro")).unwrap();
+    fs::write(src.path().join("ro/file.txt"), "inside").unwrap();
+    fs::set_permissions(src.path().join("ro"), fs::Permissions::from_mode(0o555)).unwrap();
+
+    let opts = SyncOptions::from_config(&SyncConfig::default());
+    sync_path(&NativeBackend, src.path(), dst.path(), &opts).unwrap();
+
+    assert!(fs::symlink_metadata(dst.path().join("pipe")).is_err());
+    assert_eq!(
+        fs::read_to_string(dst.path().join("ro/file.txt")).unwrap(),
+        "inside"
+    );
+    let mode = fs::metadata(dst.path().join("ro"))
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o555);
+    let summary = diff_path(&NativeBackend, src.path(), dst.path(), &opts).unwrap();
+    assert_eq!(summary.added + summary.changed + summary.deleted, 0);
+
+    for dir in [&src, &dst] {
+        fs::set_permissions(dir.path().join("ro"), fs::Permissions::from_mode(0o755)).unwrap();
+    }
+}
+
+#[test]
+fn native_sync_keeps_symlink_mtimes_and_unreadable_modes() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::write(src.path().join("secret"), "hidden").unwrap();
+    fs::set_permissions(src.path().join("secret"), fs::Permissions::from_mode(0o200)).unwrap();
+    fs::create_dir(src.path().join("dropbox")).unwrap();
+    fs::write(src.path().join("dropbox/in"), "mail").unwrap();
+    fs::set_permissions(
+        src.path().join("dropbox"),
+        fs::Permissions::from_mode(0o311),
+    )
+    .unwrap();
+    std::os::unix::fs::symlink("secret", src.path().join("link")).unwrap();
+    let old = nix::sys::time::TimeSpec::new(1_000_000_000, 0);
+    nix::sys::stat::utimensat(
+        None,
+        &src.path().join("link"),
+        &old,
+        &old,
+        nix::sys::stat::UtimensatFlags::NoFollowSymlink,
+    )
+    .unwrap();
+
+    let opts = SyncOptions::from_config(&SyncConfig::default());
+    sync_path(&NativeBackend, src.path(), dst.path(), &opts).unwrap();
+
+    let meta = |p: &Path| fs::symlink_metadata(p).unwrap();
+    assert_eq!(meta(&dst.path().join("link")).mtime(), 1_000_000_000);
+    assert_eq!(meta(&dst.path().join("secret")).mode() & 0o777, 0o200);
+    assert_eq!(meta(&dst.path().join("dropbox")).mode() & 0o777, 0o311);
+    assert_eq!(
+        meta(&dst.path().join("dropbox")).mtime(),
+        meta(&src.path().join("dropbox")).mtime()
+    );
+
+    for dir in [&src, &dst] {
+        fs::set_permissions(
+            dir.path().join("dropbox"),
+            fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+    }
+}
+
+#[test]
+fn native_sync_refuses_to_replace_a_dir_holding_excluded_entries() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::write(src.path().join("out"), "now a file").unwrap();
+    fs::create_dir(dst.path().join("out")).unwrap();
+    fs::write(dst.path().join("out/keep.log"), "excluded").unwrap();
+
+    let opts = SyncOptions {
+        exclude: vec!["*.log".to_string()],
+        ..SyncOptions::from_config(&SyncConfig::default())
+    };
+    let err = sync_path(&NativeBackend, src.path(), dst.path(), &opts).unwrap_err();
+
+    assert!(
+        format!("{err:#}").contains("cannot replace directory"),
+        "{err:#}"
+    );
+    assert!(dst.path().join("out/keep.log").exists());
+}
+
+#[test]
+fn native_plan_sees_edits_within_the_same_second() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::write(src.path().join("a.txt"), "one").unwrap();
+    fs::write(dst.path().join("a.txt"), "two").unwrap();
+    let second = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+    for (dir, nanos) in [(&src, 500_000_000), (&dst, 250_000_000)] {
+        let file = fs::File::options()
+            .write(true)
+            .open(dir.path().join("a.txt"))
+            .unwrap();
+        file.set_modified(second + std::time::Duration::from_nanos(nanos))
+            .unwrap();
+    }
+    let opts = SyncOptions::from_config(&SyncConfig::default());
+    let changes = NativeBackend.plan(src.path(), dst.path(), &opts).unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].kind, ChangeKind::Modified);
+}
+
+#[test]
+fn native_plan_reports_typed_changes() {
+    let src = tempdir().unwrap();
+    let dst = tempdir().unwrap();
+    fs::write(src.path().join("new.txt"), "12345").unwrap();
+    fs::write(src.path().join("same.txt"), "abc").unwrap();
+    fs::write(dst.path().join("gone.txt"), "xy").unwrap();
+    let opts = SyncOptions::from_config(&SyncConfig::default());
+    sync_path(&NativeBackend, src.path(), dst.path(), &opts).unwrap();
+    fs::write(src.path().join("same.txt"), "abcdef").unwrap();
+    fs::write(dst.path().join("gone.txt"), "xy").unwrap();
+
+    let changes = NativeBackend.diff(src.path(), dst.path(), &opts).unwrap();
+    assert_eq!(
+        changes,
+        vec![
+            FileChange {
+                path: PathBuf::from("same.txt"),
+                kind: ChangeKind::Modified,
+                file_type: FileType::File,
+                size_delta: 3,
+            },
+            FileChange {
+                path: PathBuf::from("gone.txt"),
+                kind: ChangeKind::Deleted,
+                file_type: FileType::File,
+                size_delta: -2,
+            },
+        ]
+    );
+}
+
+/// In-memory backend: trees are maps from path to contents keyed by root.
+#[derive(Default)]
+struct FakeBackend {
+    trees: RefCell<BTreeMap<PathBuf, BTreeMap<PathBuf, String>>>,
+}
+
+impl SyncBackend for FakeBackend {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    fn plan(&self, source: &Path, dest: &Path, opts: &SyncOptions) -> Result<Vec<FileChange>> {
+        let trees = self.trees.borrow();
+        let empty = BTreeMap::new();
+        let src = trees.get(source).unwrap_or(&empty);
+        let dst = trees.get(dest).unwrap_or(&empty);
+        let mut changes = Vec::new();
+        for (path, body) in src {
+            let kind = match dst.get(path) {
+                None => ChangeKind::Added,
+                Some(old) if old != body => ChangeKind::Modified,
+                Some(_) => continue,
+            };
+            let old_len = dst.get(path).map_or(0, |b| b.len() as i64);
+            changes.push(FileChange {
+                path: path.clone(),
+                kind,
+                file_type: FileType::File,
+                size_delta: body.len() as i64 - old_len,
+            });
+        }
+        if opts.delete {
+            for (path, body) in dst.iter().filter(|(p, _)| !src.contains_key(*p)) {
+                changes.push(FileChange {
+                    path: path.clone(),
+                    kind: ChangeKind::Deleted,
+                    file_type: FileType::File,
+                    size_delta: -(body.len() as i64),
+                });
+            }
+        }
+        Ok(changes)
+    }
+
+    fn apply(
+        &self,
+        source: &Path,
+        dest: &Path,
+        changes: &[FileChange],
+        _opts: &SyncOptions,
+    ) -> Result<()> {
+        let mut trees = self.trees.borrow_mut();
+        let src = trees.get(source).cloned().unwrap_or_default();
+        let dst = trees.entry(dest.to_path_buf()).or_default();
+        for change in changes {
+            match change.kind {
+                ChangeKind::Deleted => {
+                    dst.remove(&change.path);
+                }
+                _ => {
+                    dst.insert(change.path.clone(), src[&change.path].clone());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn custom_backend_plugs_into_sync_path() {
+    let fake = FakeBackend::default();
+    let (ram, disk) = (Path::new("/ram"), Path::new("/disk"));
+    fake.trees.borrow_mut().insert(
+        ram.to_path_buf(),
+        BTreeMap::from([
+            (PathBuf::from("a.txt"), "new".to_string()),
+            (PathBuf::from("b.txt"), "same".to_string()),
+        ]),
+    );
+    fake.trees.borrow_mut().insert(
+        disk.to_path_buf(),
+        BTreeMap::from([
+            (PathBuf::from("b.txt"), "same".to_string()),
+            (PathBuf::from("c.txt"), "stale".to_string()),
+        ]),
+    );
+    let opts = SyncOptions::from_config(&SyncConfig::default());
+
+    let summary = diff_path(&fake, ram, disk, &opts).unwrap();
+    assert_eq!((summary.added, summary.changed, summary.deleted), (1, 0, 1));
+
+    sync_path(&fake, ram, disk, &opts).unwrap();
+    assert_eq!(fake.trees.borrow()[disk], fake.trees.borrow()[ram]);
+}
+
+#[test]
+fn itemize_parsing_counts_new_files_as_added() {
+    use ramws::syncer::parse_itemize_line;
+    assert_eq!(
+        parse_itemize_line(">f+++++++++ src/new.rs"),
+        Some((
+            ChangeKind::Added,
+            FileType::File,
+            PathBuf::from("src/new.rs")
+        ))
+    );
+    assert_eq!(
+        parse_itemize_line(">f.st...... src/lib.rs"),
+        Some((
+            ChangeKind::Modified,
+            FileType::File,
+            PathBuf::from("src/lib.rs")
+        ))
+    );
+    assert_eq!(
+        parse_itemize_line("*deleting   old/"),
+        Some((ChangeKind::Deleted, FileType::Dir, PathBuf::from("old")))
+    );
+    assert_eq!(parse_itemize_line(".d..t...... ./"), None);
+}
+
+#[test]
+fn patterns_rebase_onto_subpaths() {
+    use ramws::native::rebase_patterns;
+    let patterns = vec![
+        "/pkg/gen/**".to_string(),
+        "/docs/**".to_string(),
+        "node_modules/**".to_string(),
+        "pkg/tmp/**".to_string(),
+    ];
+    assert_eq!(
+        rebase_patterns(&patterns, Path::new("pkg")),
+        vec![
+            "/gen/**".to_string(),
+            "/tmp/**".to_string(),
+            "node_modules/**".to_string(),
+            "pkg/tmp/**".to_string(),
+        ]
+    );
+}
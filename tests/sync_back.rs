@@ -0,0 +1,211 @@
+use ramws::journal::{Journal, JournalPlan};
+use ramws::syncer::{ChangeKind, FileChange, FileType};
+use std::fs;
+
+mod common;
+use common::Fixture;
+
+#[test]
+fn sync_back_keeps_excluded_paths_on_disk() {
+    let fx = Fixture::new("");
+    fx.write_disk("src/main.rs", "main");
+    fx.write_disk(".git/HEAD", "ref: refs/heads/main");
+    fx.write_disk("node_modules/dep/index.js", "dep");
+    fx.ramws().arg("start").assert().success();
+    assert!(!fx.ram(".git/HEAD").exists());
+    fs::write(fx.ram("src/main.rs"), "edited").unwrap();
+    // Without a baseline every difference counts as a RAM change, so only the
+    // source filters stand between sync back and the excluded directories.
+    fs::remove_file(fx.disk(".ramws/baseline.json")).unwrap();
+
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("src/main.rs"), "edited");
+    assert!(fx.disk(".git/HEAD").exists());
+    assert!(fx.disk("node_modules/dep/index.js").exists());
+}
+
+#[test]
+fn only_subpath_uses_rebased_source_filters() {
+    let fx = Fixture::new("sources:\n  - path: .\n    exclude:\n      - /pkg/gen/**\n");
+    fx.write_disk("pkg/lib.rs", "lib");
+    fx.write_disk("pkg/gen/out.rs", "generated");
+    fx.ramws().arg("start").assert().success();
+    assert!(!fx.ram("pkg/gen/out.rs").exists());
+    fs::write(fx.ram("pkg/lib.rs"), "edited").unwrap();
+    fs::remove_file(fx.disk(".ramws/baseline.json")).unwrap();
+
+    fx.ramws()
+        .args(["sync", "--back", "--only", "pkg", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("pkg/lib.rs"), "edited");
+    assert_eq!(fx.read_disk("pkg/gen/out.rs"), "generated");
+}
+
+#[test]
+fn protected_paths_are_never_deleted() {
+    let fx = Fixture::new("sync:\n  protect:\n    - \"*.local\"\n");
+    fx.write_disk("config.local", "secret");
+    fx.write_disk("src/old.rs", "old");
+    fx.ramws().arg("start").assert().success();
+    fs::remove_file(fx.ram("config.local")).unwrap();
+    fs::remove_file(fx.ram("src/old.rs")).unwrap();
+
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert!(fx.disk("config.local").exists());
+    assert!(!fx.disk("src/old.rs").exists());
+}
+
+#[test]
+fn mass_delete_is_refused_without_override() {
+    let fx = Fixture::new("sync:\n  max_delete: 40%\n");
+    for name in ["a", "b", "c", "d"] {
+        fx.write_disk(&format!("src/{name}.rs"), name);
+    }
+    fx.write_disk("README", "readme");
+    fx.ramws().arg("start").assert().success();
+    for name in ["a", "b", "c"] {
+        fs::remove_file(fx.ram(&format!("src/{name}.rs"))).unwrap();
+    }
+
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("3 of 6 tracked files"))
+        .stderr(predicates::str::contains("src/"));
+    assert!(fx.disk("src/a.rs").exists());
+
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive", "--allow-mass-delete"])
+        .assert()
+        .success();
+    assert!(!fx.disk("src/a.rs").exists());
+    assert!(fx.disk("src/d.rs").exists());
+}
+
+#[test]
+fn refused_mass_delete_writes_no_conflict_copies() {
+    let fx = Fixture::new("sync:\n  max_delete: 1\n  conflict: keep-both\n");
+    for name in ["a", "b", "c"] {
+        fx.write_disk(&format!("src/{name}.rs"), name);
+    }
+    fx.ramws().arg("start").assert().success();
+    fs::write(fx.ram("src/a.rs"), "ram edit").unwrap();
+    fx.write_disk("src/a.rs", "disk edit");
+    fs::remove_file(fx.ram("src/b.rs")).unwrap();
+    fs::remove_file(fx.ram("src/c.rs")).unwrap();
+
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("refusing to sync back"));
+    assert!(!fx.disk("src/a.rs.ramws-ours").exists());
+    assert!(!fx.disk("src/a.rs.ramws-theirs").exists());
+    assert!(fx.disk("src/b.rs").exists());
+}
+
+#[test]
+fn empty_source_root_never_empties_disk() {
+    let fx = Fixture::new("sources:\n  - path: src\n");
+    fx.write_disk("src/a.rs", "a");
+    fx.write_disk("src/b.rs", "b");
+    fx.ramws().arg("start").assert().success();
+    fs::remove_dir_all(fx.ram("src")).unwrap();
+    fs::create_dir(fx.ram("src")).unwrap();
+
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive", "--allow-mass-delete"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("src/a.rs"), "a");
+    assert_eq!(fx.read_disk("src/b.rs"), "b");
+}
+
+fn load_config(fx: &Fixture) -> ramws::config::ResolvedConfig {
+    ramws::config::Config::load_from_file(&fx.disk(".ramws.yml"), fx.project.path().to_path_buf())
+        .unwrap()
+}
+
+fn modified(path: &str) -> JournalPlan {
+    JournalPlan {
+        rel: ".".into(),
+        changes: vec![FileChange {
+            path: path.into(),
+            kind: ChangeKind::Modified,
+            file_type: FileType::File,
+            size_delta: 0,
+        }],
+    }
+}
+
+#[test]
+fn interrupted_sync_rolls_forward_from_staging() {
+    let fx = Fixture::new("");
+    fx.write_disk("a.txt", "old");
+    fx.ramws().arg("start").assert().success();
+    let cfg = load_config(&fx);
+    let staging = cfg.staging_dir();
+    fs::create_dir_all(&staging).unwrap();
+    fs::write(staging.join("a.txt"), "new").unwrap();
+    fx.write_disk(".a.txt.ramws-tmp", "ne");
+    Journal {
+        staging: staging.clone(),
+        backup: None,
+        plans: vec![modified("a.txt")],
+    }
+    .begin(&cfg)
+    .unwrap();
+
+    // Read-only commands leave the interrupted sync alone.
+    fx.ramws()
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("sync back was interrupted"));
+    assert_eq!(fx.read_disk("a.txt"), "old");
+    assert!(Journal::location(&cfg).exists());
+
+    fx.ramws().args(["sync", "--from"]).assert().success();
+    assert_eq!(fx.read_disk("a.txt"), "new");
+    assert!(!fx.disk(".a.txt.ramws-tmp").exists());
+    assert!(!Journal::location(&cfg).exists());
+    assert!(!staging.exists());
+}
+
+#[test]
+fn interrupted_sync_rolls_back_without_staging() {
+    let fx = Fixture::new("");
+    fx.write_disk("a.txt", "old");
+    fx.ramws().arg("start").assert().success();
+    fs::write(fx.ram("a.txt"), "new").unwrap();
+    fx.ramws()
+        .args(["sync", "--back", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("a.txt"), "new");
+    let cfg = load_config(&fx);
+    let backup = ramws::backup::Backup::find(&cfg, None).unwrap();
+    // Pretend the machine went down mid-sync and took the RAM staging with it.
+    Journal {
+        staging: cfg.staging_dir(),
+        backup: Some(backup.id),
+        plans: vec![modified("a.txt")],
+    }
+    .begin(&cfg)
+    .unwrap();
+
+    fx.ramws().arg("diff").assert().success();
+    assert_eq!(fx.read_disk("a.txt"), "new");
+
+    fx.ramws().args(["sync", "--from"]).assert().success();
+    assert_eq!(fx.read_disk("a.txt"), "old");
+    assert!(!Journal::location(&cfg).exists());
+}
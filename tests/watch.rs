@@ -0,0 +1,179 @@
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::fs;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+mod common;
+use common::Fixture;
+
+fn spawn_watch(fx: &Fixture, args: &[&str]) -> Child {
+    spawn_watch_to(fx, args, Stdio::null())
+}
+
+fn spawn_watch_to(fx: &Fixture, args: &[&str], stderr: Stdio) -> Child {
+    let child = Command::new(assert_cmd::cargo::cargo_bin!("ramws"))
+        .arg("--chdir")
+        .arg(fx.project.path())
+        .arg("watch")
+        .args(args)
+        .stderr(stderr)
+        .spawn()
+        .unwrap();
+    // Give the watcher time to register its inotify watches.
+    sleep(Duration::from_millis(500));
+    child
+}
+
+fn stop(mut child: Child) {
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM).unwrap();
+    assert!(child.wait().unwrap().success());
+}
+
+fn eventually(mut check: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        if check() {
+            return true;
+        }
+        sleep(Duration::from_millis(50));
+    }
+    false
+}
+
+fn read(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok()
+}
+
+#[test]
+fn watch_pushes_source_edits_but_not_build_dirs() {
+    let fx = Fixture::new(
+        "sync:\n  debounce_ms: 100\nbuild_dirs:\n  - path: build\n    type: scratch\n",
+    );
+    fx.write_disk("src/main.rs", "main");
+    fx.write_disk("src/old.rs", "old");
+    fx.ramws().arg("start").assert().success();
+    let child = spawn_watch(&fx, &[]);
+
+    fs::write(fx.ram("src/main.rs"), "edited").unwrap();
+    fs::remove_file(fx.ram("src/old.rs")).unwrap();
+    fs::create_dir_all(fx.ram("src/new")).unwrap();
+    fs::write(fx.ram("src/new/mod.rs"), "new").unwrap();
+    fs::write(fx.ram("build/out.o"), "object").unwrap();
+
+    assert!(eventually(
+        || read(&fx.disk("src/main.rs")).as_deref() == Some("edited")
+    ));
+    assert!(eventually(|| !fx.disk("src/old.rs").exists()));
+    assert!(eventually(
+        || read(&fx.disk("src/new/mod.rs")).as_deref() == Some("new")
+    ));
+
+    // Edits still inside the debounce window are flushed on shutdown.
+    fs::write(fx.ram("src/main.rs"), "last").unwrap();
+    stop(child);
+    assert_eq!(fx.read_disk("src/main.rs"), "last");
+    assert!(!fx.disk("build/out.o").exists());
+}
+
+#[test]
+fn watch_from_pulls_disk_edits_and_stops_on_ram_edits() {
+    let fx = Fixture::new("sync:\n  debounce_ms: 100\n");
+    fx.write_disk("src/lib.rs", "lib");
+    fx.write_disk("src/shared.rs", "shared");
+    fx.ramws().arg("start").assert().success();
+    let mut child = spawn_watch(&fx, &["--from"]);
+
+    fx.write_disk("src/lib.rs", "pulled");
+    fx.write_disk("src/added.rs", "added");
+    assert!(eventually(
+        || read(&fx.ram("src/lib.rs")).as_deref() == Some("pulled")
+    ));
+    assert!(eventually(
+        || read(&fx.ram("src/added.rs")).as_deref() == Some("added")
+    ));
+
+    fs::write(fx.ram("src/shared.rs"), "ram edit").unwrap();
+    fx.write_disk("src/shared.rs", "disk edit");
+    assert!(eventually(|| child.try_wait().unwrap().is_some()));
+    assert!(!child.wait().unwrap().success());
+    assert_eq!(read(&fx.ram("src/shared.rs")).as_deref(), Some("ram edit"));
+}
+
+#[test]
+fn watch_keeps_one_backup_of_the_disk_before_the_session() {
+    let fx = Fixture::new("sync:\n  debounce_ms: 100\nbackup:\n  keep: 1\n");
+    fx.write_disk("src/a.rs", "a0");
+    fx.write_disk("src/b.rs", "b0");
+    fx.ramws().arg("start").assert().success();
+    let child = spawn_watch(&fx, &[]);
+
+    for round in 1..=3 {
+        let body = format!("a{round}");
+        fs::write(fx.ram("src/a.rs"), &body).unwrap();
+        assert!(eventually(
+            || read(&fx.disk("src/a.rs")).as_deref() == Some(body.as_str())
+        ));
+    }
+    fs::write(fx.ram("src/b.rs"), "b1").unwrap();
+    assert!(eventually(
+        || read(&fx.disk("src/b.rs")).as_deref() == Some("b1")
+    ));
+    stop(child);
+
+    let list = fx
+        .ramws()
+        .args(["--json", "backups", "list"])
+        .output()
+        .unwrap();
+    let backups: serde_json::Value = serde_json::from_slice(&list.stdout).unwrap();
+    assert_eq!(backups.as_array().unwrap().len(), 1);
+    fx.ramws()
+        .args(["undo", "--noninteractive"])
+        .assert()
+        .success();
+    assert_eq!(fx.read_disk("src/a.rs"), "a0");
+    assert_eq!(fx.read_disk("src/b.rs"), "b0");
+}
+
+#[test]
+fn watch_reports_failed_flushes_and_retries_them() {
+    let fx = Fixture::new("sync:\n  debounce_ms: 100\n");
+    fx.write_disk("src/shared.rs", "base");
+    fx.write_disk("src/other.rs", "other");
+    fx.ramws().arg("start").assert().success();
+    let child = spawn_watch_to(&fx, &[], Stdio::piped());
+
+    fx.write_disk("src/shared.rs", "disk edit");
+    fs::write(fx.ram("src/shared.rs"), "ram edit").unwrap();
+    sleep(Duration::from_millis(500));
+    assert_eq!(fx.read_disk("src/shared.rs"), "disk edit");
+
+    // Resolve by hand: make the disk copy identical to the RAM one.
+    fs::copy(fx.ram("src/shared.rs"), fx.disk("src/shared.rs")).unwrap();
+    let mtime = fs::metadata(fx.ram("src/shared.rs"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    fs::File::options()
+        .write(true)
+        .open(fx.disk("src/shared.rs"))
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+    // The failed paths are still pending and go out with the next edit.
+    fs::write(fx.ram("src/other.rs"), "other edit").unwrap();
+    assert!(eventually(
+        || read(&fx.disk("src/other.rs")).as_deref() == Some("other edit")
+    ));
+
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("not on disk yet; retrying"), "{stderr}");
+    assert!(stderr.contains("src/shared.rs"), "{stderr}");
+    assert!(stderr.contains("synced 2 paths back to disk"), "{stderr}");
+}